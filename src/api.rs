@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "ssr")]
 use crate::mail as fs;
+#[cfg(feature = "ssr")]
+use crate::store::StoreError;
 
 #[cfg(feature = "ssr")]
 pub fn mailboxes_path() -> Result<fs::Mailboxes, ServerFnError> {
@@ -11,40 +13,601 @@ pub fn mailboxes_path() -> Result<fs::Mailboxes, ServerFnError> {
         .map(|path| fs::Mailboxes { path })
 }
 
+#[cfg(feature = "ssr")]
+fn search_index() -> Result<crate::search::SharedIndex, ServerFnError> {
+    use_context::<crate::search::SharedIndex>()
+        .ok_or_else(|| ServerFnError::ServerError("Missing context: search index".into()))
+}
+
+#[cfg(feature = "ssr")]
+fn events() -> Result<crate::events::Events, ServerFnError> {
+    use_context::<crate::events::Events>()
+        .ok_or_else(|| ServerFnError::ServerError("Missing context: events".into()))
+}
+
+#[cfg(feature = "ssr")]
+fn store() -> Result<crate::store::Store, ServerFnError> {
+    Ok(crate::store::Store {
+        mailboxes: mailboxes_path()?,
+        index: search_index()?,
+        events: events()?,
+    })
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub struct Mailbox {
     pub id: String,
     pub unread: i64,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct Domain {
+    pub id: String,
+    pub unread: i64,
+    pub mailboxes: Vec<Mailbox>,
+}
+
+/// Returns the domain part of a mailbox id or an empty string if the
+/// mailbox id is not an address.
+pub fn domain_of(mailbox: &str) -> &str {
+    mailbox
+        .rsplit_once('@')
+        .map(|(_, domain)| domain)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub struct MailboxItem {
     pub subject: String,
     pub id: String,
     pub read: bool,
+    /// Id of the conversation the mail belongs to.
+    #[serde(default)]
+    pub thread: String,
+    /// Number of mails of the thread, only set by threaded listings where
+    /// the item stands for its whole thread.
+    #[serde(default)]
+    pub thread_size: Option<usize>,
+}
+
+/// Default number of mails per page of a mailbox listing.
+pub const PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Page of a mailbox listing.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct MailboxPage {
+    pub items: Vec<MailboxItem>,
+    /// Cursor of the next page, `None` if this is the last page.
+    pub next: Option<String>,
+}
+
+/// Order of the mails within a mailbox listing.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub enum MailboxSort {
+    /// Time the mail was received by the server.
+    #[default]
+    Received,
+    /// Date header of the mail.
+    Date,
+    Sender,
+    Subject,
+}
+
+/// Filters and order of a mailbox listing. All filters must match.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "ssr", into_params(parameter_in = Query))]
+#[serde(default)]
+pub struct MailboxQuery {
+    /// Case-insensitive substring of the From header.
+    pub sender: Option<String>,
+    /// Case-insensitive substring of the subject or a regular expression if
+    /// `subject_regex` is set.
+    pub subject: Option<String>,
+    pub subject_regex: bool,
+    /// Mails received at or after this time, see [`parse_timestamp`].
+    pub since: Option<String>,
+    /// Mails received before this time, see [`parse_timestamp`].
+    pub until: Option<String>,
+    pub read: Option<bool>,
+    pub has_attachment: Option<bool>,
+    /// Name of a header which must be present.
+    pub header: Option<String>,
+    /// Case-insensitive substring of the value of `header`.
+    pub header_value: Option<String>,
+    /// Only mails of this thread.
+    pub thread: Option<String>,
+    /// Lists one item per thread instead of every mail.
+    pub threaded: bool,
+    pub sort: MailboxSort,
+    pub ascending: bool,
+}
+
+impl MailboxQuery {
+    /// Whether the query lists all mails, newest first.
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Parses a timestamp given either as milliseconds since the Unix epoch or
+/// as ISO 8601 date (`2023-09-04`) or date-time (`2023-09-04T19:43`,
+/// `2023-09-04T19:43:21+02:00`). Times without offset are UTC.
+pub fn parse_timestamp(value: &str) -> Option<u64> {
+    let value = value.trim();
+
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        return value.parse().ok();
+    }
+
+    let (date, time) = match value.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, time),
+        None => (value, ""),
+    };
+
+    // fixed width fields, which also rules out overflows further down
+    let number = |value: &str, len: usize| -> Option<i64> {
+        if value.len() == len && value.bytes().all(|b| b.is_ascii_digit()) {
+            value.parse().ok()
+        } else {
+            None
+        }
+    };
+
+    let mut parts = date.split('-');
+    let year = number(parts.next()?, 4)?;
+    let month = number(parts.next()?, 2)?;
+    let day = number(parts.next()?, 2)?;
+
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else if let Some(pos) = time.rfind(['+', '-']) {
+        let (time, offset) = time.split_at(pos);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let digits = number(&offset[1..].replace(':', ""), 4)?;
+        let (hours, minutes) = (digits / 100, digits % 100);
+
+        (time, sign * (hours * 3600 + minutes * 60))
+    } else {
+        (time, 0)
+    };
+
+    let (mut hours, mut minutes, mut seconds, mut millis) = (0, 0, 0, 0);
+
+    if !time.is_empty() {
+        let mut parts = time.split(':');
+        hours = number(parts.next()?, 2)?;
+        minutes = number(parts.next()?, 2)?;
+
+        if let Some(part) = parts.next() {
+            let (secs, fraction) = match part.split_once('.') {
+                Some((secs, fraction)) => (secs, Some(fraction)),
+                None => (part, None),
+            };
+            seconds = number(secs, 2)?;
+
+            if let Some(fraction) = fraction {
+                // checked before slicing it by bytes
+                if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                millis = format!("{:0<3}", &fraction[..fraction.len().min(3)])
+                    .parse()
+                    .ok()?;
+            }
+        }
+
+        if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+            return None;
+        }
+    }
+
+    // days since the epoch of the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hours * 3600 + minutes * 60 + seconds - offset;
+
+    u64::try_from(secs * 1000 + millis).ok()
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub struct Mail {
     pub html: Option<String>,
     pub text: Option<String>,
     pub raw: Option<String>,
-    pub attachments: Vec<String>,
+    pub attachments: Vec<Attachment>,
     pub metadata: Metadata,
+    pub headers: Vec<Header>,
+    pub structure: Option<Part>,
+    /// Messages embedded into the mail, e.g. forwarded messages.
+    pub messages: Vec<MailboxItem>,
+    /// Id of the mail containing this mail as an embedded message.
+    pub parent: Option<String>,
+    pub calendars: Vec<Calendar>,
+}
+
+/// Calendar object (iCalendar) contained in a part of a mail.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Calendar {
+    /// Id of the part containing the calendar.
+    pub part: usize,
+    pub method: Option<String>,
+    /// Timezones defined by the calendar.
+    pub timezones: Vec<String>,
+    pub events: Vec<CalendarEvent>,
+    /// Problems with the structure of the calendar.
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct CalendarEvent {
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub status: Option<String>,
+    pub sequence: Option<u32>,
+    pub organizer: Option<Attendee>,
+    pub attendees: Vec<Attendee>,
+    pub start: Option<CalendarTime>,
+    pub end: Option<CalendarTime>,
+    /// Recurrence rule (RRULE) as found in the event.
+    pub recurrence: Option<String>,
+    /// Problems with the event, e.g. missing required properties.
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Attendee {
+    pub name: Option<String>,
+    pub address: Option<String>,
+    pub role: Option<String>,
+    /// Participation status (PARTSTAT), e.g. `NEEDS-ACTION`.
+    pub status: Option<String>,
+    pub rsvp: bool,
+}
+
+impl std::fmt::Display for Attendee {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.name, &self.address) {
+            (Some(name), Some(address)) => write!(f, "{} <{}>", name, address),
+            (Some(name), None) => write!(f, "{}", name),
+            (None, Some(address)) => write!(f, "{}", address),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct CalendarTime {
+    /// Value as found in the event, e.g. `20230904T100000Z`.
+    pub value: String,
+    /// Timezone id (TZID) or `UTC` for UTC times.
+    pub timezone: Option<String>,
+    pub all_day: bool,
+}
+
+impl std::fmt::Display for CalendarTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.value.trim_end_matches('Z');
+
+        match (value.get(0..4), value.get(4..6), value.get(6..8)) {
+            (Some(year), Some(month), Some(day)) => write!(f, "{}-{}-{}", year, month, day)?,
+            _ => return write!(f, "{}", self.value),
+        }
+
+        if let (Some(hour), Some(minute)) = (value.get(9..11), value.get(11..13)) {
+            write!(f, " {}:{}", hour, minute)?;
+        }
+
+        if let Some(timezone) = &self.timezone {
+            write!(f, " ({})", timezone)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Mail found by a full-text search.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct SearchHit {
+    pub mailbox: String,
+    pub mail: MailboxItem,
+    pub from: String,
+    pub date: Option<String>,
+    /// Part of the body around the first match.
+    pub snippet: String,
+    pub score: f32,
+}
+
+/// Seconds the wait endpoint waits for a mail by default.
+pub const WAIT_TIMEOUT: u64 = 30;
+pub const MAX_WAIT_TIMEOUT: u64 = 300;
+
+/// Criteria of the mail to wait for, all given criteria must match.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "ssr", into_params(parameter_in = Query))]
+pub struct WaitQuery {
+    /// Recipient of the mail.
+    #[serde(alias = "recipient")]
+    pub mailbox: Option<String>,
+    pub domain: Option<String>,
+    /// Case-insensitive substring of the sender.
+    pub sender: Option<String>,
+    /// Case-insensitive regular expression matching the subject.
+    pub subject: Option<String>,
+    /// Mails received at or after this time, see [`parse_timestamp`].
+    pub since: Option<String>,
+    /// Seconds to wait, at most [`MAX_WAIT_TIMEOUT`].
+    pub timeout: Option<u64>,
+}
+
+/// Mail found by waiting for it.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct WaitedMail {
+    pub mailbox: String,
+    pub mail: MailboxItem,
+}
+
+/// Body of the error responses of the REST API.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct ErrorBody {
+    /// HTTP status code.
+    pub status: u16,
+    pub error: String,
+}
+
+/// Percent encodes a path segment, e.g. a mailbox or attachment name
+/// within a link.
+pub fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Returns the path of a mail relative to its mailbox directory.
+/// Embedded messages are stored within their parent mail.
+pub fn mail_path(mail: &str) -> String {
+    mail.split('.').collect::<Vec<_>>().join("/messages/")
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Attachment {
+    /// File name within the attachments directory.
+    pub name: String,
+    pub content_type: String,
+    #[serde(default)]
+    pub charset: Option<String>,
+    /// Size of the decoded content.
+    pub size: usize,
+    /// Size declared by the `size` parameter of the Content-Disposition.
+    pub declared_size: Option<usize>,
+    pub encoding: Option<String>,
+    /// Hex encoded SHA-256 hash of the decoded content.
+    pub sha256: String,
+    /// Id of the part containing the attachment, not available for mails
+    /// stored before attachment metadata was recorded.
+    pub part: Option<usize>,
+}
+
+/// Attachments larger than this are only offered for download.
+pub const PREVIEW_LIMIT: usize = 1024 * 1024;
+
+/// How an attachment is previewed within the browser.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+pub enum PreviewKind {
+    Image,
+    Pdf,
+    Text,
+    Csv,
+    Json,
+    VCard,
+}
+
+impl Attachment {
+    /// Returns how the attachment can be previewed or `None` if it can only
+    /// be downloaded. Generic content types are refined by the file
+    /// extension.
+    pub fn preview_kind(&self) -> Option<PreviewKind> {
+        let extension = self
+            .name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_lowercase());
+
+        let kind = match self.content_type.as_str() {
+            "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/bmp"
+            | "image/svg+xml" | "image/avif" | "image/x-icon" => PreviewKind::Image,
+            "application/pdf" => PreviewKind::Pdf,
+            "text/csv" | "text/comma-separated-values" | "application/csv" => PreviewKind::Csv,
+            "application/json" | "text/json" => PreviewKind::Json,
+            ty if ty.ends_with("+json") => PreviewKind::Json,
+            "text/vcard" | "text/x-vcard" | "text/directory" => PreviewKind::VCard,
+            ty @ ("text/plain" | "application/octet-stream") => match extension.as_deref() {
+                Some("csv") => PreviewKind::Csv,
+                Some("json") => PreviewKind::Json,
+                Some("vcf") | Some("vcard") => PreviewKind::VCard,
+                _ if ty == "text/plain" => PreviewKind::Text,
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        (self.size <= PREVIEW_LIMIT).then_some(kind)
+    }
+}
+
+/// Content of an attachment prepared for a preview. Images and PDFs are
+/// displayed by the browser itself and have no prepared content.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct Preview {
+    pub content: PreviewContent,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub enum PreviewContent {
+    Text(String),
+    /// Rows of a CSV file, the first row is the header.
+    Table(Vec<Vec<String>>),
+    /// Pretty printed JSON document.
+    Json(String),
+    Contacts(Vec<Contact>),
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct Contact {
+    pub name: Option<String>,
+    pub fields: Vec<Header>,
+}
+
+/// Node of the MIME structure of a mail.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Part {
+    /// Index of the part within the message.
+    pub id: usize,
+    pub content_type: String,
+    pub charset: Option<String>,
+    pub encoding: Option<String>,
+    pub disposition: Option<String>,
+    pub name: Option<String>,
+    pub content_id: Option<String>,
+    /// Size of the (encoded) body within the message.
+    pub size: usize,
+    /// Size of the decoded content, not available for multipart parts.
+    pub decoded_size: Option<usize>,
+    /// Path of the decoded content relative to the mail directory.
+    pub file: Option<String>,
+    /// Id of the mail of an embedded message.
+    pub message: Option<String>,
+    pub headers: Vec<Header>,
+    pub children: Vec<Part>,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
 pub struct Metadata {
     pub id: String,
     pub subject: String,
     pub from: String,
     pub date: Option<String>,
+    pub to: Vec<Address>,
+    pub cc: Vec<Address>,
+    pub reply_to: Vec<Address>,
+    pub message_id: Option<String>,
+    pub in_reply_to: Vec<String>,
+    pub references: Vec<String>,
+    pub list: Vec<Header>,
+    /// Charsets declared by the body parts.
+    pub charsets: Vec<String>,
+    /// Problems encountered while decoding the mail.
+    pub warnings: Vec<String>,
 }
 
-#[server(GetMailboxes, "/api")]
-pub async fn get_mailboxes() -> Result<Vec<Mailbox>, ServerFnError> {
-    let mailboxes = mailboxes_path()?;
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Address {
+    pub name: Option<String>,
+    pub address: Option<String>,
+    /// Name of the group the address was listed in, if any. Empty groups
+    /// are kept as an entry without name and address.
+    #[serde(default)]
+    pub group: Option<String>,
+}
 
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.name, &self.address) {
+            (Some(name), Some(address)) => write!(f, "{} <{}>", name, address),
+            (Some(name), None) => write!(f, "{}", name),
+            (None, Some(address)) => write!(f, "{}", address),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Formats a list of addresses like an address header, e.g. `a@b.c,
+/// Friends: d@e.f, g@h.i;`.
+pub fn display_addresses(addresses: &[Address]) -> String {
+    let mut out = String::new();
+    let mut group: Option<&str> = None;
+
+    for addr in addresses {
+        if group.is_some() && group != addr.group.as_deref() {
+            out.push(';');
+            group = None;
+        }
+
+        if !out.is_empty() {
+            out.push_str(", ");
+        }
+
+        if group.is_none() {
+            if let Some(name) = addr.group.as_deref() {
+                out.push_str(name);
+                out.push(':');
+                group = Some(name);
+
+                if addr.name.is_some() || addr.address.is_some() {
+                    out.push(' ');
+                }
+            }
+        }
+
+        out.push_str(&addr.to_string());
+    }
+
+    if group.is_some() {
+        out.push(';');
+    }
+
+    out
+}
+
+/// Header as it appears in the raw message (name and unparsed value).
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+/// Lists all mailboxes sorted by their id.
+#[cfg(feature = "ssr")]
+pub fn list_mailboxes(mailboxes: &fs::Mailboxes) -> Result<Vec<Mailbox>, fs::MailError> {
     let mut vec = mailboxes
         .mailboxes()?
         .into_iter()
@@ -54,74 +617,748 @@ pub async fn get_mailboxes() -> Result<Vec<Mailbox>, ServerFnError> {
                 unread: m.unread()?,
             })
         })
-        .collect::<Result<Vec<_>, crate::mail::MailError>>()?;
+        .collect::<Result<Vec<_>, fs::MailError>>()?;
 
     vec.sort_by(|a, b| a.id.cmp(&b.id));
 
     Ok(vec)
 }
 
-#[server(GetMailbox, "/api")]
-pub async fn get_mailbox(mailbox: String) -> Result<Option<Vec<MailboxItem>>, ServerFnError> {
-    let mailboxes = mailboxes_path()?;
+#[server(GetMailboxes, "/api")]
+pub async fn get_mailboxes() -> Result<Vec<Domain>, ServerFnError> {
+    let vec = list_mailboxes(&mailboxes_path()?)?;
 
-    let data = match mailboxes.mailbox(&mailbox)? {
-        Some(mailbox) => {
-            let mut vec = mailbox
-                .mails()?
-                .into_iter()
-                .map(|mail| {
-                    let read = mail.read()?;
-                    mail.metadata()
-                        .map(|metadata| MailboxItem {
-                            subject: metadata.subject,
-                            id: metadata.id,
-                            read,
-                        })
-                        .map_err(|e| ServerFnError::ServerError(format!("{}", e)))
+    let mut domains: Vec<Domain> = Vec::new();
+
+    for mailbox in vec {
+        let domain = domain_of(&mailbox.id);
+
+        match domains.iter_mut().find(|d| d.id == domain) {
+            Some(entry) => {
+                entry.unread += mailbox.unread;
+                entry.mailboxes.push(mailbox);
+            }
+            None => domains.push(Domain {
+                id: domain.to_string(),
+                unread: mailbox.unread,
+                mailboxes: vec![mailbox],
+            }),
+        }
+    }
+
+    domains.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(domains)
+}
+
+/// Filter of a [`MailboxQuery`] prepared for matching mails.
+#[cfg(feature = "ssr")]
+struct MailFilter<'a> {
+    query: &'a MailboxQuery,
+    subject_regex: Option<regex::Regex>,
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+/// Mail of a listing with the key it is sorted by.
+#[cfg(feature = "ssr")]
+struct ListEntry {
+    item: MailboxItem,
+    key: String,
+    received: u64,
+}
+
+#[cfg(feature = "ssr")]
+impl<'a> MailFilter<'a> {
+    fn new(query: &'a MailboxQuery) -> Result<Self, StoreError> {
+        let timestamp = |value: &Option<String>, name: &str| {
+            value
+                .as_ref()
+                .map(|v| {
+                    parse_timestamp(v).ok_or_else(|| {
+                        StoreError::Invalid(format!("invalid timestamp for `{}`", name))
+                    })
+                })
+                .transpose()
+        };
+
+        let subject_regex = match (&query.subject, query.subject_regex) {
+            (Some(subject), true) => Some(
+                regex::RegexBuilder::new(subject)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|err| StoreError::Invalid(format!("invalid regex: {}", err)))?,
+            ),
+            _ => None,
+        };
+
+        Ok(Self {
+            query,
+            subject_regex,
+            since: timestamp(&query.since, "since")?,
+            until: timestamp(&query.until, "until")?,
+        })
+    }
+
+    /// Returns the entry of the mail if it matches the filter. The cheap
+    /// checks are done first to avoid reading files.
+    fn apply(
+        &self,
+        mail: &fs::MailItem,
+        received: u64,
+    ) -> Result<Option<ListEntry>, fs::MailError> {
+        let query = self.query;
+        let contains = |value: &str, pattern: &Option<String>| {
+            pattern
+                .as_ref()
+                .map(|pattern| value.to_lowercase().contains(&pattern.to_lowercase()))
+                .unwrap_or(true)
+        };
+
+        if self.since.map(|since| received < since).unwrap_or(false)
+            || self.until.map(|until| received >= until).unwrap_or(false)
+        {
+            return Ok(None);
+        }
+
+        let read = mail.read()?;
+
+        if query.read.map(|value| value != read).unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let metadata = mail.metadata()?;
+
+        if query
+            .thread
+            .as_ref()
+            .map(|thread| thread != metadata.thread())
+            .unwrap_or(false)
+        {
+            return Ok(None);
+        }
+
+        let subject_matches = match &self.subject_regex {
+            Some(regex) => regex.is_match(&metadata.subject),
+            None => contains(&metadata.subject, &query.subject),
+        };
+
+        if !subject_matches || !contains(&metadata.from, &query.sender) {
+            return Ok(None);
+        }
+
+        if let Some(has_attachment) = query.has_attachment {
+            if mail.attachments()?.is_empty() == has_attachment {
+                return Ok(None);
+            }
+        }
+
+        if let Some(name) = &query.header {
+            let found = mail.headers()?.iter().any(|header| {
+                header.name.eq_ignore_ascii_case(name)
+                    && contains(&header.value, &query.header_value)
+            });
+
+            if !found {
+                return Ok(None);
+            }
+        }
+
+        let key = match query.sort {
+            MailboxSort::Received => String::new(),
+            // zero padded to keep the numeric order, mails without a date
+            // come first
+            MailboxSort::Date => metadata
+                .date
+                .as_deref()
+                .and_then(parse_timestamp)
+                .map(|date| format!("{:020}", date))
+                .unwrap_or_default(),
+            MailboxSort::Sender => metadata.from.to_lowercase(),
+            MailboxSort::Subject => metadata.subject.to_lowercase(),
+        };
+
+        Ok(Some(ListEntry {
+            item: MailboxItem {
+                thread: metadata.thread().to_string(),
+                subject: metadata.subject,
+                id: metadata.id,
+                read,
+                thread_size: None,
+            },
+            key,
+            received,
+        }))
+    }
+}
+
+/// Replaces the entries of each thread by its first entry, which is read
+/// if all mails of the thread are read.
+#[cfg(feature = "ssr")]
+fn collapse_threads(entries: Vec<ListEntry>) -> Vec<ListEntry> {
+    let mut threads: std::collections::HashMap<String, usize> = Default::default();
+    let mut collapsed: Vec<ListEntry> = Vec::new();
+
+    for mut entry in entries {
+        match threads.get(&entry.item.thread) {
+            Some(&pos) => {
+                let item = &mut collapsed[pos].item;
+                item.thread_size = item.thread_size.map(|size| size + 1);
+                item.read &= entry.item.read;
+            }
+            None => {
+                threads.insert(entry.item.thread.clone(), collapsed.len());
+                entry.item.thread_size = Some(1);
+                collapsed.push(entry);
+            }
+        }
+    }
+
+    collapsed
+}
+
+/// Lists a page of the mails of a mailbox matching the query. The cursor
+/// is taken from [`MailboxPage::next`] of the previous page.
+///
+/// Mails sorted by their receive time are read lazily, so only as many
+/// mails are read as needed to fill the page. Threaded listings contain
+/// the first mail of each thread and always read all mails.
+#[cfg(feature = "ssr")]
+pub fn list_mailbox(
+    mailbox: &fs::Mailbox,
+    query: &MailboxQuery,
+    cursor: Option<&str>,
+    limit: usize,
+) -> Result<MailboxPage, StoreError> {
+    let filter = MailFilter::new(query)?;
+    let limit = limit.clamp(1, MAX_PAGE_SIZE);
+
+    // cursors point to the last entry of the previous page
+    let cursor = cursor
+        .map(|cursor| {
+            cursor
+                .split_once(':')
+                .and_then(|(received, key)| {
+                    received
+                        .parse::<u64>()
+                        .ok()
+                        .map(|received| (key.to_string(), received))
                 })
-                .collect::<Result<Vec<_>, _>>()?;
+                .ok_or_else(|| StoreError::Invalid(String::from("invalid cursor")))
+        })
+        .transpose()?;
+    let after_cursor = |key: &str, received: u64| match &cursor {
+        None => true,
+        Some((cursor_key, cursor_received)) => {
+            let order = (key, received).cmp(&(cursor_key.as_str(), *cursor_received));
+
+            if query.ascending {
+                order.is_gt()
+            } else {
+                order.is_lt()
+            }
+        }
+    };
+
+    let mut mails = mailbox
+        .mails()?
+        .into_iter()
+        .map(|mail| (mail.id().parse::<u64>().unwrap_or_default(), mail))
+        .collect::<Vec<_>>();
 
-            vec.sort_by(|a, b| b.id.cmp(&a.id));
+    mails.sort_by_key(|(received, _)| *received);
 
-            Some(vec)
+    if !query.ascending {
+        mails.reverse();
+    }
+
+    let mut entries = Vec::new();
+
+    if query.sort == MailboxSort::Received && !query.threaded {
+        for (received, mail) in mails {
+            if !after_cursor("", received) {
+                continue;
+            }
+
+            entries.extend(filter.apply(&mail, received)?);
+
+            if entries.len() > limit {
+                break;
+            }
+        }
+    } else {
+        for (received, mail) in mails {
+            entries.extend(filter.apply(&mail, received)?);
+        }
+
+        entries.sort_by(|a, b| {
+            let order = (&a.key, a.received).cmp(&(&b.key, b.received));
+
+            if query.ascending {
+                order
+            } else {
+                order.reverse()
+            }
+        });
+
+        if query.threaded {
+            entries = collapse_threads(entries);
         }
-        None => None,
+
+        entries.retain(|entry| after_cursor(&entry.key, entry.received));
+    }
+
+    let next = if entries.len() > limit {
+        entries.truncate(limit);
+        entries
+            .last()
+            .map(|entry| format!("{}:{}", entry.received, entry.key))
+    } else {
+        None
     };
 
-    Ok(data)
+    Ok(MailboxPage {
+        items: entries.into_iter().map(|entry| entry.item).collect(),
+        next,
+    })
+}
+
+#[server(GetMailbox, "/api")]
+pub async fn get_mailbox(
+    mailbox: String,
+    query: Option<MailboxQuery>,
+    cursor: Option<String>,
+    limit: Option<usize>,
+) -> Result<Option<MailboxPage>, ServerFnError> {
+    let mailboxes = mailboxes_path()?;
+    let query = query.unwrap_or_default();
+
+    mailboxes
+        .mailbox(&mailbox)?
+        .map(|mailbox| {
+            list_mailbox(
+                &mailbox,
+                &query,
+                cursor.as_deref(),
+                limit.unwrap_or(PAGE_SIZE),
+            )
+        })
+        .transpose()
+        .map_err(|err| err.into())
+}
+
+/// Reads the content of a mail.
+#[cfg(feature = "ssr")]
+pub fn mail_content(mail: &fs::MailItem) -> Result<Mail, fs::MailError> {
+    let metadata = mail.metadata()?;
+    let mut warnings = metadata.warnings;
+
+    Ok(Mail {
+        html: mail.html()?.map(|v| v.into_content(&mut warnings)),
+        text: mail.text()?.map(|v| v.into_content(&mut warnings)),
+        raw: Some(
+            mail.raw_text(&metadata.charsets)?
+                .into_content(&mut warnings),
+        ),
+        attachments: mail.attachment_metadata()?,
+        headers: mail.headers()?,
+        structure: mail.structure()?,
+        messages: mail
+            .messages()?
+            .into_iter()
+            .map(|mail| {
+                let read = mail.read()?;
+                mail.metadata().map(|metadata| MailboxItem {
+                    thread: metadata.thread().to_string(),
+                    subject: metadata.subject,
+                    id: metadata.id,
+                    read,
+                    thread_size: None,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        parent: mail.parent_id(),
+        calendars: mail.calendars()?,
+        metadata: Metadata {
+            id: metadata.id,
+            subject: metadata.subject,
+            from: metadata.from,
+            date: metadata.date,
+            to: metadata.to,
+            cc: metadata.cc,
+            reply_to: metadata.reply_to,
+            message_id: metadata.message_id,
+            in_reply_to: metadata.in_reply_to,
+            references: metadata.references,
+            list: metadata.list,
+            charsets: metadata.charsets,
+            warnings,
+        },
+    })
 }
 
+/// Opens a mail and marks it as read.
 #[server(GetMail, "/api")]
 pub async fn get_mail(mailbox: String, mail: String) -> Result<Option<Mail>, ServerFnError> {
+    let store = store()?;
+
+    let item = match store
+        .mailboxes
+        .mailbox(&mailbox)?
+        .and_then(|mailbox| mailbox.mail(&mail).transpose())
+        .transpose()?
+    {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+
+    // only mails which could be shown are marked as read
+    let content = mail_content(&item)?;
+
+    if !store.set_read(&mailbox, &mail, true)? {
+        return Ok(None);
+    }
+
+    Ok(Some(content))
+}
+
+#[server(GetAttachmentPreview, "/api")]
+pub async fn get_attachment_preview(
+    mailbox: String,
+    mail: String,
+    name: String,
+) -> Result<Option<Preview>, ServerFnError> {
     let mailboxes = mailboxes_path()?;
 
-    let mail = mailboxes
+    let mail = match mailboxes
         .mailbox(&mailbox)?
         .and_then(|mailbox| mailbox.mail(&mail).transpose())
         .transpose()?
-        .map(|mail| {
-            if !mail.read()? {
-                mail.set_read()?;
-            }
+    {
+        Some(mail) => mail,
+        None => return Ok(None),
+    };
+
+    let attachment = match mail
+        .attachment_metadata()?
+        .into_iter()
+        .find(|attachment| attachment.name == name)
+    {
+        Some(attachment) => attachment,
+        None => return Ok(None),
+    };
+
+    let kind = match attachment.preview_kind() {
+        Some(PreviewKind::Image) | Some(PreviewKind::Pdf) | None => {
+            return Err(ServerFnError::ServerError(format!(
+                "no preview available for attachment `{}`",
+                name
+            )))
+        }
+        Some(kind) => kind,
+    };
+
+    let mut warnings = Vec::new();
+    let text = mail
+        .attachment_text(&attachment)?
+        .into_content(&mut warnings);
 
-            Ok::<_, fs::MailError>(Mail {
-                html: mail.html()?,
-                text: mail.text()?,
-                raw: String::from_utf8(mail.raw()?).ok(),
-                attachments: mail.attachments()?.into_iter().map(|a| a.id()).collect(),
-                metadata: {
-                    let val = mail.metadata()?;
-                    Metadata {
-                        id: val.id,
-                        subject: val.subject,
-                        from: val.from,
-                        date: val.date,
-                    }
+    Ok(Some(crate::preview::preview(kind, text, warnings)))
+}
+
+/// Maximum number of hits returned by a search.
+pub const SEARCH_LIMIT: usize = 100;
+
+/// Searches all mails, see [`crate::search`] for the query syntax.
+#[server(Search, "/api")]
+pub async fn search(query: String) -> Result<Vec<SearchHit>, ServerFnError> {
+    let mailboxes = mailboxes_path()?;
+    let hits = search_index()?
+        .read()
+        .map_err(|_| ServerFnError::ServerError("search index is poisoned".into()))?
+        .search(&query, SEARCH_LIMIT);
+
+    hits.into_iter()
+        .map(|hit| {
+            let read = mailboxes
+                .mailbox(&hit.mailbox)?
+                .and_then(|mailbox| mailbox.mail(&hit.mail).transpose())
+                .transpose()?
+                .map(|mail| mail.read())
+                .transpose()?
+                .unwrap_or(false);
+
+            Ok(SearchHit {
+                mailbox: hit.mailbox,
+                mail: MailboxItem {
+                    subject: hit.subject,
+                    id: hit.mail,
+                    read,
+                    thread: hit.thread,
+                    thread_size: None,
                 },
+                from: hit.from,
+                date: hit.date,
+                snippet: hit.snippet,
+                score: hit.score,
             })
         })
-        .transpose()?;
+        .collect::<Result<Vec<_>, fs::MailError>>()
+        .map_err(|err| err.into())
+}
+
+/// Marks a mail as read or unread. Returns false if the mail does not
+/// exist.
+#[server(SetRead, "/api")]
+pub async fn set_read(mailbox: String, mail: String, read: bool) -> Result<bool, ServerFnError> {
+    store()?
+        .set_read(&mailbox, &mail, read)
+        .map_err(|err| err.into())
+}
+
+/// Marks all mails of a mailbox as read. Returns false if the mailbox does
+/// not exist.
+#[server(MarkMailboxRead, "/api")]
+pub async fn mark_mailbox_read(mailbox: String) -> Result<bool, ServerFnError> {
+    store()?
+        .mark_mailbox_read(&mailbox)
+        .map_err(|err| err.into())
+}
+
+/// Deletes a mail. Embedded messages are part of their mail and can not be
+/// deleted on their own. Returns false if the mail does not exist.
+#[server(DeleteMail, "/api")]
+pub async fn delete_mail(mailbox: String, mail: String) -> Result<bool, ServerFnError> {
+    store()?
+        .delete_mail(&mailbox, &mail)
+        .map_err(|err| err.into())
+}
+
+/// Deletes all mails of a mailbox but keeps the mailbox. Returns false if
+/// the mailbox does not exist.
+#[server(EmptyMailbox, "/api")]
+pub async fn empty_mailbox(mailbox: String) -> Result<bool, ServerFnError> {
+    store()?.empty_mailbox(&mailbox).map_err(|err| err.into())
+}
+
+/// Deletes a mailbox with all of its mails. Returns false if the mailbox
+/// does not exist.
+#[server(DeleteMailbox, "/api")]
+pub async fn delete_mailbox(mailbox: String) -> Result<bool, ServerFnError> {
+    store()?.delete_mailbox(&mailbox).map_err(|err| err.into())
+}
+
+/// Deletes all mailboxes.
+#[server(Purge, "/api")]
+pub async fn purge() -> Result<(), ServerFnError> {
+    store()?.purge().map_err(|err| err.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_path_segments() {
+        assert_eq!(encode_path_segment("john@example.com"), "john@example.com");
+        assert_eq!(
+            encode_path_segment("invoice #3?.pdf"),
+            "invoice%20%233%3F.pdf"
+        );
+        assert_eq!(encode_path_segment("a/b%c"), "a%2Fb%25c");
+        assert_eq!(encode_path_segment("grüße"), "gr%C3%BC%C3%9Fe");
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("1693822981000"), Some(1693822981000));
+        assert_eq!(parse_timestamp(" 1970-01-01 "), Some(0));
+        assert_eq!(parse_timestamp("2023-09-04T10:23"), Some(1693822980000));
+        assert_eq!(
+            parse_timestamp("2023-09-04 10:23:01.5Z"),
+            Some(1693822981500)
+        );
+        assert_eq!(
+            parse_timestamp("2023-09-04T10:23:01+02:00"),
+            Some(1693815781000)
+        );
+        assert_eq!(
+            parse_timestamp("2023-09-04T10:23:01-0530"),
+            Some(1693842781000)
+        );
+        assert_eq!(
+            parse_timestamp("2000-02-29T23:59:59.99999999999999999999"),
+            Some(951868799999)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        for value in [
+            "",
+            "yesterday",
+            "1969-12-31",
+            "2023-9-4",
+            "2023-13-01",
+            "2023-09-04-01",
+            "99999999999999-01-01",
+            "2023-09-04T24:00",
+            "2023-09-04T10:60",
+            "2023-09-04T10:00:00:00",
+            "2023-09-04T10:00:00.",
+            "2023-09-04T10:00:00.-1",
+            "2023-09-04T10:00+2",
+        ] {
+            assert_eq!(parse_timestamp(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_non_ascii_timestamps() {
+        for value in [
+            "2023-09-04T10:00:00.é",
+            "2023-09-04T10:00:00.1é",
+            "2023-09-04T10:00+0é:0",
+            "2023-09-04T10:00+é0:0",
+            "2023-09-04T1é:00",
+            "２０２３-09-04",
+        ] {
+            assert_eq!(parse_timestamp(value), None, "{}", value);
+        }
+    }
+
+    /// Mailbox `test` with mails named by their receive time, subject and
+    /// thread.
+    #[cfg(feature = "ssr")]
+    fn mailbox(name: &str, mails: &[(u64, &str, &str)]) -> fs::Mailboxes {
+        let path = std::env::temp_dir().join(format!(
+            "mail-blackhole-api-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+
+        for (received, subject, thread) in mails {
+            let mail = path.join("test").join(received.to_string());
+            std::fs::create_dir_all(&mail).unwrap();
+
+            let raw = format!(
+                "From: sender@example.com\r\nTo: test@example.com\r\nSubject: {}\r\n\r\nbody\r\n",
+                subject
+            );
+            let message = mail_parser::Message::parse(raw.as_bytes()).unwrap();
+
+            fs::MailItem::create(
+                mail,
+                &message,
+                subject.to_string(),
+                Some(thread.to_string()),
+            )
+            .unwrap();
+        }
+
+        fs::Mailboxes { path }
+    }
+
+    #[cfg(feature = "ssr")]
+    #[test]
+    fn pages_through_mailbox() {
+        let mailboxes = mailbox(
+            "pages",
+            &[
+                (1, "b", "1"),
+                (2, "a", "2"),
+                (3, "b", "1"),
+                (4, "c", "4"),
+                (5, "a", "2"),
+                (6, "b", "6"),
+                (7, "a", "7"),
+            ],
+        );
+        let mailbox = mailboxes.mailbox("test").unwrap().unwrap();
+
+        let queries = [
+            MailboxQuery::default(),
+            MailboxQuery {
+                ascending: true,
+                ..Default::default()
+            },
+            MailboxQuery {
+                sort: MailboxSort::Subject,
+                ..Default::default()
+            },
+            MailboxQuery {
+                sort: MailboxSort::Subject,
+                ascending: true,
+                ..Default::default()
+            },
+            MailboxQuery {
+                threaded: true,
+                ..Default::default()
+            },
+            MailboxQuery {
+                subject: Some(String::from("b")),
+                ..Default::default()
+            },
+        ];
+
+        for query in queries {
+            let all = list_mailbox(&mailbox, &query, None, MAX_PAGE_SIZE).unwrap();
+            assert_eq!(all.next, None);
+
+            for limit in 1..=all.items.len() {
+                let mut items = Vec::new();
+                let mut cursor = None;
+
+                loop {
+                    let page = list_mailbox(&mailbox, &query, cursor.as_deref(), limit).unwrap();
+                    assert!(page.items.len() <= limit);
+
+                    items.extend(page.items);
+                    cursor = match page.next {
+                        Some(next) => Some(next),
+                        None => break,
+                    };
+                }
+
+                assert_eq!(items, all.items, "{:?} by {}", query, limit);
+            }
+        }
+
+        let ids = |query: MailboxQuery| {
+            list_mailbox(&mailbox, &query, None, MAX_PAGE_SIZE)
+                .unwrap()
+                .items
+                .into_iter()
+                .map(|item| item.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids(MailboxQuery::default()),
+            ["7", "6", "5", "4", "3", "2", "1"]
+        );
+        assert_eq!(
+            ids(MailboxQuery {
+                sort: MailboxSort::Subject,
+                ascending: true,
+                ..Default::default()
+            }),
+            ["2", "5", "7", "1", "3", "6", "4"]
+        );
+        assert_eq!(
+            ids(MailboxQuery {
+                threaded: true,
+                ..Default::default()
+            }),
+            ["7", "6", "5", "4", "3"]
+        );
+
+        assert!(matches!(
+            list_mailbox(&mailbox, &MailboxQuery::default(), Some("nonsense"), 1),
+            Err(StoreError::Invalid(_))
+        ));
 
-    Ok(mail)
+        std::fs::remove_dir_all(&mailboxes.path).unwrap();
+    }
 }
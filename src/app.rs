@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::ops::Deref;
 
 use leptos::*;
@@ -7,16 +8,21 @@ use leptos_router::*;
 
 use crate::{api, QueueItem};
 
+/// Latest change of the store received from the server.
 #[cfg(not(feature = "ssr"))]
 #[derive(Clone)]
-struct MailItemNewEvent(leptos::ReadSignal<Option<QueueItem>>);
+struct StoreEvent(leptos::ReadSignal<Option<QueueItem>>);
 
 #[cfg(feature = "ssr")]
 #[derive(Clone)]
-struct MailItemNewEvent(leptos::ReadSignal<Option<QueueItem>>);
+struct StoreEvent(leptos::ReadSignal<Option<QueueItem>>);
+
+/// Number of times the server reported lost events.
+#[derive(Clone)]
+struct Resync(leptos::ReadSignal<Option<usize>>);
 
 #[cfg(not(feature = "ssr"))]
-fn sse_events() -> MailItemNewEvent {
+fn sse_events() -> (StoreEvent, Resync) {
     use futures::stream::StreamExt;
 
     let mut source = gloo_net::eventsource::futures::EventSource::new("/sse")
@@ -31,28 +37,50 @@ fn sse_events() -> MailItemNewEvent {
                         match serde_json::from_str::<QueueItem>(&data) {
                             Ok(val) => Some(val),
                             Err(err) => {
-                                leptos::logging::error!("failed to parse new mail event: {}", err);
+                                leptos::logging::error!("failed to parse store event: {}", err);
                                 None
                             }
                         }
                     }),
                     Err(err) => {
-                        leptos::logging::error!("stream for new mail is broken: {}", err);
+                        leptos::logging::error!("stream of store events is broken: {}", err);
                         None
                     }
                 }
             })
             .boxed_local(),
     );
+    let resync = create_signal_from_stream(
+        source
+            .subscribe("resync")
+            .unwrap()
+            .enumerate()
+            .map(|(count, _)| count)
+            .boxed_local(),
+    );
 
     on_cleanup(move || source.close());
-    MailItemNewEvent(s)
+    (StoreEvent(s), Resync(resync))
 }
 
 #[cfg(feature = "ssr")]
-fn sse_events() -> MailItemNewEvent {
+fn sse_events() -> (StoreEvent, Resync) {
     let (s, _) = create_signal(None);
-    MailItemNewEvent(s)
+    let (resync, _) = create_signal(None);
+    (StoreEvent(s), Resync(resync))
+}
+
+/// Reloads the resource whenever events were lost.
+fn refetch_on_resync<S: Clone + 'static, T: 'static>(resource: Resource<S, T>) {
+    let resync = use_context::<Resync>()
+        .expect("to have found the resync provided")
+        .0;
+
+    create_effect(move |_| {
+        if resync.get().is_some() {
+            resource.refetch();
+        }
+    });
 }
 
 #[derive(Clone)]
@@ -61,12 +89,91 @@ struct SetMailbox(WriteSignal<Option<String>>);
 #[derive(Clone)]
 struct SetMail(WriteSignal<Option<String>>);
 
-#[derive(Clone)]
-struct ReadMail(WriteSignal<Option<String>>);
+/// Applies a change of the store to the unread counts of the mailboxes.
+fn update_domains(domains: &mut Vec<api::Domain>, event: &QueueItem) {
+    // embedded messages do not count as mails of the mailbox
+    let count = |mails: &[String]| mails.iter().filter(|id| !id.contains('.')).count() as i64;
+
+    match event {
+        QueueItem::MailCreated { mailbox, .. } => add_unread(domains, mailbox, 1),
+        QueueItem::MailboxCreated { mailbox } => add_unread(domains, mailbox, 0),
+        QueueItem::MailRead { mailbox, mails } => add_unread(domains, mailbox, -count(mails)),
+        QueueItem::MailUnread { mailbox, mails } => add_unread(domains, mailbox, count(mails)),
+        QueueItem::MailDeleted {
+            mailbox, unread, ..
+        } => add_unread(domains, mailbox, -unread),
+        QueueItem::MailboxRemoved { mailbox } => {
+            let domain_id = api::domain_of(mailbox);
+
+            if let Some(idx) = domains.iter().position(|domain| domain.id == domain_id) {
+                let domain = &mut domains[idx];
+
+                if let Some(pos) = domain.mailboxes.iter().position(|v| &v.id == mailbox) {
+                    let removed = domain.mailboxes.remove(pos);
+                    domain.unread -= removed.unread;
+                }
+
+                if domain.mailboxes.is_empty() {
+                    domains.remove(idx);
+                }
+            }
+        }
+        QueueItem::StorePurged => domains.clear(),
+    }
+}
+
+/// Changes the unread count of a mailbox, which is added if it is missing.
+fn add_unread(domains: &mut Vec<api::Domain>, mailbox: &str, unread: i64) {
+    let domain_id = api::domain_of(mailbox);
+
+    let domain = match domains.iter().position(|domain| domain.id == domain_id) {
+        Some(idx) => &mut domains[idx],
+        None => {
+            let idx = domains
+                .iter()
+                .position(|domain| domain.id.as_str() > domain_id)
+                .unwrap_or(domains.len());
+
+            domains.insert(
+                idx,
+                crate::api::Domain {
+                    id: domain_id.to_string(),
+                    unread: 0,
+                    mailboxes: Vec::new(),
+                },
+            );
+
+            &mut domains[idx]
+        }
+    };
+
+    domain.unread += unread;
+
+    match domain.mailboxes.iter_mut().find(|v| v.id == mailbox) {
+        Some(entry) => {
+            entry.unread += unread;
+        }
+        None => {
+            let idx = domain
+                .mailboxes
+                .iter()
+                .position(|v| v.id.as_str() > mailbox)
+                .unwrap_or(domain.mailboxes.len());
+
+            domain.mailboxes.insert(
+                idx,
+                crate::api::Mailbox {
+                    id: mailbox.to_string(),
+                    unread,
+                },
+            );
+        }
+    }
+}
 
 #[component]
 fn Mailboxes() -> impl IntoView {
-    let change_event = use_context::<MailItemNewEvent>()
+    let change_event = use_context::<StoreEvent>()
         .expect("to have found the change_event provided")
         .0;
 
@@ -74,102 +181,114 @@ fn Mailboxes() -> impl IntoView {
 
     provide_context(SetMailbox(set_selected_mailbox));
 
-    let (read_mail, set_read_mail) = create_signal(Option::<String>::None);
+    let (collapsed, set_collapsed) = create_signal(HashSet::<String>::new());
 
-    provide_context(ReadMail(set_read_mail));
+    let (query, set_query) = create_signal(String::new());
 
     let data = create_resource(
         move || (),
         move |_| async move { api::get_mailboxes().await },
     );
 
+    refetch_on_resync(data);
+
     create_effect(move |_| {
-        if let Some(item) = change_event.get() {
+        if let Some(event) = change_event.get() {
             data.update(|val| {
-                if let Some(Ok(ref mut vec)) = val {
-                    match vec.iter_mut().find(|mailbox| mailbox.id == item.receiver) {
-                        Some(mailbox) => {
-                            mailbox.unread += 1;
-                        }
-                        None => {
-                            let opt = vec
-                                .iter()
-                                .enumerate()
-                                .find(|(_, mailbox)| mailbox.id > item.receiver);
-
-                            let insert = crate::api::Mailbox {
-                                id: item.receiver,
-                                unread: 1,
-                            };
-
-                            match opt {
-                                Some((idx, _)) => {
-                                    vec.insert(idx, insert);
-                                }
-                                None => {
-                                    vec.push(insert);
-                                }
-                            }
-                        }
-                    }
+                if let Some(Ok(ref mut domains)) = val {
+                    update_domains(domains, &event);
                 }
             })
         }
     });
 
-    create_effect(move |_| {
-        if let Some(id) = read_mail.get() {
-            data.update(|val| {
-                if let Some(Ok(ref mut vec)) = val {
-                    for mailbox in vec {
-                        if mailbox.id == id {
-                            mailbox.unread -= 1;
-                        }
-                    }
-                }
-            })
+    let unread_string = |unread: i64| {
+        if unread > 0 {
+            format!(" ({})", unread)
+        } else {
+            String::from("")
         }
-    });
+    };
 
     let content = move || {
         data.get().map(|result| match result {
             Err(e) => view! { <p class="error">{e.to_string()}</p> }.into_view(),
-            Ok(mailboxes) => {
-                if mailboxes.is_empty() {
+            Ok(domains) => {
+                if domains.is_empty() {
                     view! { <p class="empty">"No mailboxes found."</p> }.into_view()
                 } else {
-                    mailboxes
+                    domains
                         .into_iter()
-                        .map(|mailbox| {
-                            let unread_string = move || {
-                                let unread = mailbox.unread;
-                                if unread > 0 {
-                                    format!(" ({})", unread)
-                                } else {
-                                    String::from("")
+                        .map(|domain| {
+                            let domain_id = domain.id;
+                            let is_collapsed = collapsed.with(|value| value.contains(&domain_id));
+
+                            let classes = if domain.unread > 0 {
+                                "domain unread"
+                            } else {
+                                "domain"
+                            };
+
+                            let toggle = {
+                                let domain_id = domain_id.clone();
+                                move |_| {
+                                    set_collapsed.update(|value| {
+                                        if !value.remove(&domain_id) {
+                                            value.insert(domain_id.clone());
+                                        }
+                                    })
                                 }
                             };
-                            let mailbox_id = mailbox.id;
 
-                            let classes = {
-                                let unread = mailbox.unread;
+                            let mailboxes = if is_collapsed {
+                                ().into_view()
+                            } else {
+                                domain
+                                    .mailboxes
+                                    .into_iter()
+                                    .map(|mailbox| {
+                                        let mailbox_id = mailbox.id;
 
-                                if selected_mailbox
-                                    .with(|value| value.as_ref().map(|v| v == &mailbox_id))
-                                    .unwrap_or(false)
-                                {
-                                    "selected"
-                                } else if unread > 0 {
-                                    "unread"
-                                } else {
-                                    ""
-                                }
+                                        let classes = {
+                                            let unread = mailbox.unread;
+
+                                            if selected_mailbox
+                                                .with(|value| {
+                                                    value.as_ref().map(|v| v == &mailbox_id)
+                                                })
+                                                .unwrap_or(false)
+                                            {
+                                                "selected"
+                                            } else if unread > 0 {
+                                                "unread"
+                                            } else {
+                                                ""
+                                            }
+                                        };
+
+                                        view! {
+                                          <A class=classes href=format!("/{mailbox_id}")>
+                                            <span>{mailbox_id} {unread_string(mailbox.unread)}</span>
+                                          </A>
+                                        }
+                                    })
+                                    .collect_view()
+                            };
+
+                            let name = if domain_id.is_empty() {
+                                String::from("(no domain)")
+                            } else {
+                                domain_id
                             };
 
                             view! {
-                              <A class=classes href=format!("/{mailbox_id}")>
-                                <span>{mailbox_id} {unread_string}</span>
-                              </A>
+                              <button class=classes on:click=toggle>
+                                <span>
+                                  {if is_collapsed { "▸ " } else { "▾ " }} {name}
+                                  {unread_string(domain.unread)}
+                                </span>
+                              </button>
+                              {mailboxes}
                             }
                         })
                         .collect_view()
@@ -182,31 +301,119 @@ fn Mailboxes() -> impl IntoView {
         view! { <div class="box">{content}</div> }.on_mount(|html| {
             let el = web_sys::Element::from(html.deref().clone());
 
-            match el.query_selector(".selected") {
-                Ok(Some(selected)) => selected.scroll_into_view(),
-                _ => {}
+            if let Ok(Some(selected)) = el.query_selector(".selected") {
+                selected.scroll_into_view();
             }
         })
     };
 
+    let list = move || {
+        if query.with(|query| query.trim().is_empty()) {
+            view! { <Suspense fallback=|| {}>{inner}</Suspense> }.into_view()
+        } else {
+            view! { <SearchResults query=query/> }.into_view()
+        }
+    };
+
     view! {
       <>
         <nav>
-          <Suspense fallback=|| {}>{inner}</Suspense>
+          <form class="search" on:submit=|ev| ev.prevent_default()>
+            <input
+              type="search"
+              placeholder="Search mails"
+              prop:value=query
+              on:input=move |ev| set_query.set(event_target_value(&ev))
+            />
+            <button
+              type="button"
+              title="Delete all mailboxes"
+              on:click=move |_| {
+                  if confirm("Delete all mailboxes and mails?") {
+                      spawn_local(async move {
+                          if let Err(err) = api::purge().await {
+                              leptos::logging::error!("failed to purge: {}", err);
+                          }
+                      });
+                  }
+              }
+            >
+              "Purge"
+            </button>
+          </form>
+          {list}
         </nav>
         <Outlet/>
       </>
     }
 }
 
+/// Lists the mails of all mailboxes matching the search query.
 #[component]
-fn Mailbox() -> impl IntoView {
-    let change_event = use_context::<MailItemNewEvent>()
-        .expect("to have found the mail_item_new_event provided")
+fn SearchResults(query: ReadSignal<String>) -> impl IntoView {
+    let change_event = use_context::<StoreEvent>()
+        .expect("to have found the change_event provided")
         .0;
 
-    let set_read_mail = use_context::<ReadMail>()
-        .expect("to have found the read_mail provided")
+    let results = create_resource(
+        move || query.get(),
+        move |query| async move { api::search(query).await },
+    );
+
+    refetch_on_resync(results);
+
+    // new mails may match the query as well
+    create_effect(move |prev: Option<()>| {
+        change_event.track();
+
+        if prev.is_some() {
+            results.refetch();
+        }
+    });
+
+    let content = move || {
+        results.get().map(|result| match result {
+            Err(e) => view! { <p class="error">{e.to_string()}</p> }.into_view(),
+            Ok(hits) => {
+                if hits.is_empty() {
+                    view! { <p class="empty">"No mails found."</p> }.into_view()
+                } else {
+                    hits.into_iter()
+                        .map(|hit| {
+                            let classes = if hit.mail.read { "" } else { "unread" };
+
+                            view! {
+                              <A class=classes href=format!("/{}/{}", hit.mailbox, hit.mail.id)>
+                                <div class="search-hit">
+                                  <b>{hit.mail.subject}</b>
+                                  <small>{format!("{} → {}", hit.from, hit.mailbox)}</small>
+                                  <small class="snippet">{hit.snippet}</small>
+                                </div>
+                              </A>
+                            }
+                        })
+                        .collect_view()
+                }
+            }
+        })
+    };
+
+    view! {
+      <Transition fallback=|| {}>
+        <div class="box">{content}</div>
+      </Transition>
+    }
+}
+
+/// Height of a row in the mail list in pixels, must match the style.
+const MAIL_ROW_HEIGHT: f64 = 42.0;
+/// Rows rendered above and below the visible part of the mail list.
+const MAIL_ROW_OVERSCAN: usize = 10;
+
+#[component]
+fn Mailbox() -> impl IntoView {
+    let change_event = use_context::<StoreEvent>()
+        .expect("to have found the change_event provided")
         .0;
 
     let set_mailbox = use_context::<SetMailbox>()
@@ -217,28 +424,176 @@ fn Mailbox() -> impl IntoView {
 
     provide_context(SetMail(set_selected_mail));
 
+    let (query, set_query) = create_signal(api::MailboxQuery::default());
+
     let params = use_params_map();
     let data = create_resource(
-        move || params.with(|q| q.get("mailbox").cloned().unwrap_or_default()),
-        move |mailbox| async move {
+        move || {
+            (
+                params.with(|q| q.get("mailbox").cloned().unwrap_or_default()),
+                query.get(),
+            )
+        },
+        move |(mailbox, query)| async move {
             if mailbox.is_empty() {
                 None
             } else {
                 set_mailbox.update(|value| *value = Some(mailbox.clone()));
-                Some((api::get_mailbox(mailbox.clone()).await, mailbox))
+                Some((
+                    api::get_mailbox(mailbox.clone(), Some(query), None, None).await,
+                    mailbox,
+                ))
             }
         },
     );
 
+    refetch_on_resync(data);
+
     create_effect(move |_| {
-        if let Some(item) = change_event.get() {
-            data.update(|val| {
-                if let Some(Some((Ok(Some(ref mut vec)), mailbox))) = val {
-                    if *mailbox == item.receiver {
-                        vec.insert(0, item.obj.clone());
+        let item = match change_event.get() {
+            Some(item) => item,
+            None => return,
+        };
+
+        let current = params.with_untracked(|q| q.get("mailbox").cloned());
+
+        if item.mailbox().is_some() && item.mailbox() != current.as_deref() {
+            return;
+        }
+
+        // only the default order allows to update the list without knowing
+        // the details of the mails
+        let update = |f: &dyn Fn(&mut api::MailboxPage)| {
+            if query.with_untracked(|query| query.is_default()) {
+                data.update(|val| {
+                    if let Some(Some((Ok(Some(ref mut page)), _))) = val {
+                        f(page);
+                    }
+                });
+            } else {
+                data.refetch();
+            }
+        };
+
+        let set_read = |mails: &[String], read: bool| {
+            update(&|page| {
+                for item in &mut page.items {
+                    if mails.contains(&item.id) {
+                        item.read = read;
                     }
                 }
             })
+        };
+
+        match item {
+            QueueItem::MailCreated { mail, .. } => {
+                update(&|page| page.items.insert(0, mail.clone()))
+            }
+            QueueItem::MailRead { mails, .. } => set_read(&mails, true),
+            QueueItem::MailUnread { mails, .. } => set_read(&mails, false),
+            QueueItem::MailDeleted { mails, .. } => {
+                update(&|page| page.items.retain(|item| !mails.contains(&item.id)))
+            }
+            QueueItem::MailboxRemoved { .. } | QueueItem::StorePurged => data.refetch(),
+            QueueItem::MailboxCreated { .. } => (),
+        }
+    });
+
+    // only the visible rows of the list are rendered, all rows have the
+    // same height
+    let list = create_node_ref::<html::Div>();
+    let (viewport, set_viewport) = create_signal((0.0, 800.0));
+    let (loading, set_loading) = create_signal(false);
+
+    let visible = create_memo(move |_| {
+        let (top, height) = viewport.get();
+        let first = (top / MAIL_ROW_HEIGHT) as usize;
+        let last = ((top + height) / MAIL_ROW_HEIGHT).ceil() as usize;
+
+        (
+            first.saturating_sub(MAIL_ROW_OVERSCAN),
+            last + MAIL_ROW_OVERSCAN,
+        )
+    });
+
+    let load_more = move || {
+        if loading.get_untracked() {
+            return;
+        }
+
+        let next = untrack(|| {
+            data.with(|val| match val {
+                Some(Some((Ok(Some(page)), mailbox))) => {
+                    page.next.clone().map(|cursor| (mailbox.clone(), cursor))
+                }
+                _ => None,
+            })
+        });
+
+        if let Some((mailbox, cursor)) = next {
+            set_loading.set(true);
+            let query = query.get_untracked();
+
+            spawn_local(async move {
+                match api::get_mailbox(mailbox.clone(), Some(query), Some(cursor.clone()), None)
+                    .await
+                {
+                    Ok(Some(more)) => data.update(|val| {
+                        if let Some(Some((Ok(Some(ref mut page)), current))) = val {
+                            // the listing may have changed meanwhile
+                            if *current == mailbox && page.next.as_ref() == Some(&cursor) {
+                                page.items.extend(more.items);
+                                page.next = more.next;
+                            }
+                        }
+                    }),
+                    Ok(None) => (),
+                    Err(err) => leptos::logging::error!("failed to load mails: {}", err),
+                }
+
+                set_loading.set(false);
+            });
+        }
+    };
+
+    // loads the next page as soon as the end of the list is visible
+    let check_scroll = move || {
+        if let Some(list) = list.get_untracked() {
+            let top = list.scroll_top() as f64;
+            let height = list.client_height() as f64;
+
+            set_viewport.set((top, height));
+
+            if top + height + MAIL_ROW_HEIGHT * 2.0 >= list.scroll_height() as f64 {
+                load_more();
+            }
+        }
+    };
+
+    create_effect(move |_| {
+        data.track();
+        loading.track();
+        check_scroll();
+    });
+
+    // scrolls to the selected mail after the listing changed
+    create_effect(move |_| {
+        let position = data.with(|val| match val {
+            Some(Some((Ok(Some(page)), _))) => selected_mail.with_untracked(|selected| {
+                selected
+                    .as_ref()
+                    .and_then(|id| page.items.iter().position(|item| &item.id == id))
+            }),
+            _ => None,
+        });
+
+        if let (Some(position), Some(list)) = (position, list.get_untracked()) {
+            let top = position as f64 * MAIL_ROW_HEIGHT;
+            let current = list.scroll_top() as f64;
+
+            if top < current || top + MAIL_ROW_HEIGHT > current + list.client_height() as f64 {
+                list.set_scroll_top(top as i32);
+            }
         }
     });
 
@@ -247,13 +602,19 @@ fn Mailbox() -> impl IntoView {
             None => view! { <div></div> }.into_view(),
             Some((Err(e), _)) => view! { <p class="error">{e.to_string()}</p> }.into_view(),
             Some((Ok(opt), mailbox)) => match opt {
-                Some(items) => {
-                    if items.is_empty() {
+                Some(page) => {
+                    if page.items.is_empty() {
                         view! { <p class="empty">"No mails found."</p> }.into_view()
                     } else {
-                        items
-                            .into_iter()
-                            .map(move |entry| {
+                        let total = page.items.len();
+                        let (first, last) = visible.get();
+                        let first = first.min(total);
+                        let last = last.min(total);
+
+                        let rows = page.items[first..last]
+                            .iter()
+                            .cloned()
+                            .map(|entry| {
                                 let classes = if selected_mail
                                     .with(|value| value.as_ref().map(|v| v == &entry.id))
                                     .unwrap_or(false)
@@ -265,22 +626,16 @@ fn Mailbox() -> impl IntoView {
                                     ""
                                 };
 
-                                let id = entry.id.clone();
+                                let thread = entry.thread.clone();
+                                let size = entry.thread_size.filter(|size| *size > 1);
 
+                                // threads open as list of their mails, the
+                                // read state is updated by the store event
                                 let handler = move |_| {
-                                    if !entry.read {
-                                        data.update(|val| {
-                                            if let Some(Some((Ok(Some(ref mut vec)), mailbox))) =
-                                                val
-                                            {
-                                                for item in vec {
-                                                    if item.id == id {
-                                                        item.read = true;
-                                                        set_read_mail.set(Some(mailbox.clone()));
-                                                        break;
-                                                    }
-                                                }
-                                            }
+                                    if size.is_some() {
+                                        set_query.update(|q| {
+                                            q.thread = Some(thread.clone());
+                                            q.threaded = false;
                                         });
                                     }
                                 };
@@ -292,10 +647,28 @@ fn Mailbox() -> impl IntoView {
                                     href=format!("/{mailbox}/{}", entry.id)
                                   >
                                     <span>{entry.subject}</span>
+                                    {size
+                                        .map(|size| {
+                                            view! { <span class="thread-size">{size}</span> }
+                                        })}
                                   </A>
                                 }
                             })
-                            .collect_view()
+                            .collect_view();
+
+                        let before = first as f64 * MAIL_ROW_HEIGHT;
+                        let after = (total - last) as f64 * MAIL_ROW_HEIGHT;
+                        let more = page.next.is_some().then(|| {
+                            view! { <p class="loading">"Loading more mails …"</p> }
+                        });
+
+                        view! {
+                          <div style=format!("height: {}px", before)></div>
+                          {rows}
+                          <div style=format!("height: {}px", after)></div>
+                          {more}
+                        }
+                        .into_view()
                     }
                 }
                 None => view! { <p class="empty">"Mailbox not found."</p> }.into_view(),
@@ -303,28 +676,595 @@ fn Mailbox() -> impl IntoView {
         })
     };
 
-    let inner = move || {
-        view! { <div>{content}</div> }.on_mount(|html| {
-            let el = web_sys::Element::from(html.deref().clone());
+    view! {
+      <>
+        <nav class="mailbox">
+          <MailboxActions/>
+          <div class="view-mode">
+            <label>
+              <input
+                type="checkbox"
+                prop:checked=move || query.with(|q| q.threaded)
+                on:change=move |ev| {
+                    set_query
+                        .update(|q| {
+                            q.threaded = event_target_checked(&ev);
+                            q.thread = None;
+                        })
+                }
+              />
+              "Group by thread"
+            </label>
+            {move || {
+                query
+                    .with(|q| q.thread.is_some())
+                    .then(|| {
+                        view! {
+                          <button class="link" on:click=move |_| set_query.update(|q| q.thread = None)>
+                            "Show all mails"
+                          </button>
+                        }
+                    })
+            }}
+          </div>
+          <MailboxFilter query=query set_query=set_query/>
+          <div class="mails box" node_ref=list on:scroll=move |_| check_scroll()>
+            <Suspense fallback=|| {}>{content}</Suspense>
+          </div>
+        </nav>
+        <Outlet/>
+      </>
+    }
+}
+
+/// Buttons changing the whole mailbox.
+#[component]
+fn MailboxActions() -> impl IntoView {
+    let params = use_params_map();
+    let navigate = store_value(use_navigate());
 
-            match el.query_selector(".selected") {
-                Ok(Some(selected)) => selected.scroll_into_view(),
-                _ => {}
+    let mailbox = move || params.with_untracked(|q| q.get("mailbox").cloned().unwrap_or_default());
+
+    let mark_read = move |_| {
+        let mailbox = mailbox();
+
+        spawn_local(async move {
+            if let Err(err) = api::mark_mailbox_read(mailbox).await {
+                leptos::logging::error!("failed to mark mailbox as read: {}", err);
+            }
+        });
+    };
+
+    let empty = move |_| {
+        let mailbox = mailbox();
+
+        if confirm(&format!("Delete all mails of {}?", mailbox)) {
+            spawn_local(async move {
+                if let Err(err) = api::empty_mailbox(mailbox).await {
+                    leptos::logging::error!("failed to empty mailbox: {}", err);
+                }
+            });
+        }
+    };
+
+    let delete = move |_| {
+        let mailbox = mailbox();
+
+        if confirm(&format!("Delete the mailbox {}?", mailbox)) {
+            spawn_local(async move {
+                match api::delete_mailbox(mailbox).await {
+                    Ok(_) => navigate.with_value(|navigate| navigate("/", Default::default())),
+                    Err(err) => leptos::logging::error!("failed to delete mailbox: {}", err),
+                }
+            });
+        }
+    };
+
+    view! {
+      <div class="actions">
+        <button class="link" on:click=mark_read>
+          "Mark all read"
+        </button>
+        <button class="link" on:click=empty>
+          "Empty"
+        </button>
+        <button class="link" on:click=delete>
+          "Delete"
+        </button>
+      </div>
+    }
+}
+
+/// Asks the user to confirm a destructive action.
+fn confirm(message: &str) -> bool {
+    window().confirm_with_message(message).unwrap_or(false)
+}
+
+/// Form editing the filters and the order of a mailbox listing.
+#[component]
+fn MailboxFilter(
+    query: ReadSignal<api::MailboxQuery>,
+    set_query: WriteSignal<api::MailboxQuery>,
+) -> impl IntoView {
+    let text = |ev: &ev::Event| Some(event_target_value(ev)).filter(|v| !v.trim().is_empty());
+    let flag = |ev: &ev::Event| match event_target_value(ev).as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    };
+    let flag_value = |value: Option<bool>| match value {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "",
+    };
+
+    view! {
+      <details class="filter box">
+        <summary>"Filter and sort"</summary>
+        <label>
+          "Sender"
+          <input
+            type="text"
+            prop:value=move || query.with(|q| q.sender.clone().unwrap_or_default())
+            on:change=move |ev| set_query.update(|q| q.sender = text(&ev))
+          />
+        </label>
+        <label>
+          "Subject"
+          <input
+            type="text"
+            prop:value=move || query.with(|q| q.subject.clone().unwrap_or_default())
+            on:change=move |ev| set_query.update(|q| q.subject = text(&ev))
+          />
+        </label>
+        <label class="inline">
+          <input
+            type="checkbox"
+            prop:checked=move || query.with(|q| q.subject_regex)
+            on:change=move |ev| set_query.update(|q| q.subject_regex = event_target_checked(&ev))
+          />
+          "Subject is a regular expression"
+        </label>
+        <label>
+          "Received since (UTC)"
+          <input
+            type="datetime-local"
+            prop:value=move || query.with(|q| q.since.clone().unwrap_or_default())
+            on:change=move |ev| set_query.update(|q| q.since = text(&ev))
+          />
+        </label>
+        <label>
+          "Received until (UTC)"
+          <input
+            type="datetime-local"
+            prop:value=move || query.with(|q| q.until.clone().unwrap_or_default())
+            on:change=move |ev| set_query.update(|q| q.until = text(&ev))
+          />
+        </label>
+        <label>
+          "Read"
+          <select
+            prop:value=move || query.with(|q| flag_value(q.read))
+            on:change=move |ev| set_query.update(|q| q.read = flag(&ev))
+          >
+            <option value="">"all"</option>
+            <option value="no">"unread"</option>
+            <option value="yes">"read"</option>
+          </select>
+        </label>
+        <label>
+          "Attachments"
+          <select
+            prop:value=move || query.with(|q| flag_value(q.has_attachment))
+            on:change=move |ev| set_query.update(|q| q.has_attachment = flag(&ev))
+          >
+            <option value="">"all"</option>
+            <option value="yes">"with attachments"</option>
+            <option value="no">"without attachments"</option>
+          </select>
+        </label>
+        <label>
+          "Header"
+          <input
+            type="text"
+            placeholder="name"
+            prop:value=move || query.with(|q| q.header.clone().unwrap_or_default())
+            on:change=move |ev| set_query.update(|q| q.header = text(&ev))
+          />
+          <input
+            type="text"
+            placeholder="value contains"
+            prop:value=move || query.with(|q| q.header_value.clone().unwrap_or_default())
+            on:change=move |ev| set_query.update(|q| q.header_value = text(&ev))
+          />
+        </label>
+        <label>
+          "Sort by"
+          <select
+            prop:value=move || {
+                query
+                    .with(|q| match q.sort {
+                        api::MailboxSort::Received => "received",
+                        api::MailboxSort::Date => "date",
+                        api::MailboxSort::Sender => "sender",
+                        api::MailboxSort::Subject => "subject",
+                    })
+            }
+            on:change=move |ev| {
+                set_query
+                    .update(|q| {
+                        q.sort = match event_target_value(&ev).as_str() {
+                            "date" => api::MailboxSort::Date,
+                            "sender" => api::MailboxSort::Sender,
+                            "subject" => api::MailboxSort::Subject,
+                            _ => api::MailboxSort::Received,
+                        };
+                    })
+            }
+          >
+            <option value="received">"received"</option>
+            <option value="date">"date header"</option>
+            <option value="sender">"sender"</option>
+            <option value="subject">"subject"</option>
+          </select>
+        </label>
+        <label class="inline">
+          <input
+            type="checkbox"
+            prop:checked=move || query.with(|q| q.ascending)
+            on:change=move |ev| set_query.update(|q| q.ascending = event_target_checked(&ev))
+          />
+          "Ascending"
+        </label>
+        <button type="button" on:click=move |_| set_query.set(api::MailboxQuery::default())>
+          "Reset"
+        </button>
+      </details>
+    }
+}
+
+fn problems_view(problems: Vec<String>) -> Option<View> {
+    (!problems.is_empty()).then(|| {
+        view! {
+          <ul class="problems">
+            {problems.into_iter().map(|problem| view! { <li>{problem}</li> }).collect_view()}
+          </ul>
+        }
+        .into_view()
+    })
+}
+
+/// Renders the events of a calendar as invitation cards.
+fn calendar_view(calendar: api::Calendar) -> View {
+    let method = calendar.method.unwrap_or_else(|| String::from("PUBLISH"));
+    let calendar_problems = problems_view(calendar.problems);
+
+    let events = calendar
+        .events
+        .into_iter()
+        .map(|event| {
+            let classes = if event.problems.is_empty() {
+                "event"
+            } else {
+                "event malformed"
+            };
+
+            let when = match (event.start, event.end) {
+                (Some(start), Some(end)) => format!("{} – {}", start, end),
+                (Some(start), None) => start.to_string(),
+                (None, Some(end)) => format!("until {}", end),
+                (None, None) => String::from("unknown"),
+            };
+
+            let row = |name: &'static str, value: Option<String>| {
+                value.map(|value| {
+                    view! {
+                      <p>
+                        <b>{name}</b>
+                        {": "}
+                        {value}
+                      </p>
+                    }
+                })
+            };
+
+            let attendees = (!event.attendees.is_empty()).then(|| {
+                let attendees = event
+                    .attendees
+                    .into_iter()
+                    .map(|attendee| {
+                        let details = [attendee.role.clone(), attendee.status.clone()]
+                            .into_iter()
+                            .flatten()
+                            .chain(attendee.rsvp.then(|| String::from("RSVP")))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+
+                        view! {
+                          <li>
+                            {attendee.to_string()} {" "} <i>{details}</i>
+                          </li>
+                        }
+                    })
+                    .collect_view();
+
+                view! {
+                  <b>Attendees:</b>
+                  <ul>{attendees}</ul>
+                }
+            });
+
+            view! {
+              <div class=classes>
+                <h3>{event.summary.unwrap_or_else(|| String::from("(no summary)"))}</h3>
+                {row("When", Some(when))}
+                {row("Repeats", event.recurrence)}
+                {row("Where", event.location)}
+                {row("Organizer", event.organizer.map(|v| v.to_string()))}
+                {row("Status", event.status)}
+                {attendees}
+                {row("Description", event.description)}
+                {problems_view(event.problems)}
+              </div>
             }
         })
+        .collect_view();
+
+    view! {
+      <div class="calendar box">
+        <p class="method">{format!("Calendar {} (part #{})", method, calendar.part)}</p>
+        {calendar_problems}
+        {events}
+      </div>
+    }
+    .into_view()
+}
+
+/// Renders an attachment link together with its metadata.
+fn attachment_view(
+    attachment: api::Attachment,
+    mailbox: &str,
+    path: &str,
+    set_preview: WriteSignal<Option<String>>,
+) -> View {
+    let preview = attachment.preview_kind().map(|_| {
+        let name = attachment.name.clone();
+        view! {
+          <button class="link" on:click=move |_| set_preview.set(Some(name.clone()))>
+            preview
+          </button>
+        }
+    });
+    let size = match attachment.declared_size {
+        Some(declared) if declared != attachment.size => {
+            format!("{} bytes (declared {} bytes)", attachment.size, declared)
+        }
+        _ => format!("{} bytes", attachment.size),
     };
+    let details = [
+        Some(attachment.content_type),
+        Some(size),
+        attachment.encoding.map(|v| format!("encoding: {}", v)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(", ");
+    let short_hash = attachment.sha256.chars().take(12).collect::<String>();
+    let href = format!(
+        "/data/{}/{path}/attachments/{}",
+        api::encode_path_segment(mailbox),
+        api::encode_path_segment(&attachment.name)
+    );
 
     view! {
-      <>
-        <nav>
-          <Suspense fallback=|| {}>
-            <div class="box">{inner}</div>
-          </Suspense>
+      <li>
+        <a href=href.clone() target="_blank">
+          <span>{attachment.name}</span>
+        </a>
+        {preview}
+        <a href=format!("{href}?download")>download</a>
+        <span class="attachment-details">{details}</span>
+        <code class="attachment-hash" title=attachment.sha256>
+          {format!("sha256: {}…", short_hash)}
+        </code>
+      </li>
+    }
+    .into_view()
+}
 
-        </nav>
-        <Outlet/>
-      </>
+/// Renders the preview of an attachment. Images and PDFs are displayed by
+/// the browser, textual formats are prepared by the server.
+fn preview_view(
+    attachment: api::Attachment,
+    mailbox: &str,
+    mail: &str,
+    set_preview: WriteSignal<Option<String>>,
+) -> View {
+    let href = format!(
+        "/data/{}/{}/attachments/{}",
+        api::encode_path_segment(mailbox),
+        api::mail_path(mail),
+        api::encode_path_segment(&attachment.name)
+    );
+
+    let body = match attachment.preview_kind() {
+        Some(api::PreviewKind::Image) => view! {
+          <img class="preview-image" src=href.clone() alt=attachment.name.clone()/>
+        }
+        .into_view(),
+        Some(api::PreviewKind::Pdf) => view! {
+          <object class="preview-pdf" type="application/pdf" data=href.clone()></object>
+        }
+        .into_view(),
+        Some(_) => {
+            let mailbox = mailbox.to_string();
+            let mail = mail.to_string();
+            let name = attachment.name.clone();
+            let content = create_resource(
+                || (),
+                move |_| api::get_attachment_preview(mailbox.clone(), mail.clone(), name.clone()),
+            );
+
+            view! {
+              <Suspense fallback=|| {}>
+                {move || content.get().map(|result| match result {
+                    Err(e) => view! { <p class="error">{e.to_string()}</p> }.into_view(),
+                    Ok(None) => view! { <p class="not-found">Attachment not found.</p> }.into_view(),
+                    Ok(Some(preview)) => preview_content_view(preview),
+                })}
+              </Suspense>
+            }
+            .into_view()
+        }
+        None => view! {
+          <p class="empty">
+            No preview available for this type, <a href=format!("{href}?download")>download</a>
+            " it instead."
+          </p>
+        }
+        .into_view(),
+    };
+
+    view! {
+      <div class="preview box">
+        <div class="preview-header">
+          <b>{attachment.name}</b>
+          <a href=format!("{href}?download")>download</a>
+          <button class="link" on:click=move |_| set_preview.set(None)>
+            close
+          </button>
+        </div>
+        {body}
+      </div>
     }
+    .into_view()
+}
+
+fn preview_content_view(preview: api::Preview) -> View {
+    let warnings = problems_view(preview.warnings);
+
+    let content = match preview.content {
+        api::PreviewContent::Text(text) => {
+            view! { <pre class="preview-text">{text}</pre> }.into_view()
+        }
+        api::PreviewContent::Json(json) => {
+            view! { <pre class="preview-text">{json}</pre> }.into_view()
+        }
+        api::PreviewContent::Table(rows) => {
+            let mut rows = rows.into_iter();
+            let head = rows.next().map(|row| {
+                view! { <tr>{row.into_iter().map(|v| view! { <th>{v}</th> }).collect_view()}</tr> }
+            });
+
+            view! {
+              <table class="preview-table">
+                <thead>{head}</thead>
+                <tbody>
+                  {rows
+                      .map(|row| {
+                          view! {
+                            <tr>{row.into_iter().map(|v| view! { <td>{v}</td> }).collect_view()}</tr>
+                          }
+                      })
+                      .collect_view()}
+                </tbody>
+              </table>
+            }
+            .into_view()
+        }
+        api::PreviewContent::Contacts(contacts) => contacts
+            .into_iter()
+            .map(|contact| {
+                view! {
+                  <div class="preview-contact">
+                    <h3>{contact.name.unwrap_or_else(|| String::from("(no name)"))}</h3>
+                    <table class="content-headers">
+                      {contact
+                          .fields
+                          .into_iter()
+                          .map(|field| {
+                              view! {
+                                <tr>
+                                  <th>{field.name}</th>
+                                  <td>{field.value}</td>
+                                </tr>
+                              }
+                          })
+                          .collect_view()}
+                    </table>
+                  </div>
+                }
+            })
+            .collect_view(),
+    };
+
+    view! {
+      {warnings}
+      {content}
+    }
+    .into_view()
+}
+
+/// Renders a MIME part and its children as nested list entries.
+fn part_view(part: api::Part, mailbox: String, base: String) -> View {
+    let details = [
+        part.name.map(|v| format!("name: {}", v)),
+        part.disposition.map(|v| format!("disposition: {}", v)),
+        part.charset.map(|v| format!("charset: {}", v)),
+        part.encoding.map(|v| format!("encoding: {}", v)),
+        part.content_id.map(|v| format!("content-id: {}", v)),
+        Some(format!("size: {} bytes", part.size)),
+        part.decoded_size.map(|v| format!("decoded: {} bytes", v)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(", ");
+
+    let links = part.file.map(|file| {
+        let href = format!("{base}/{file}");
+        view! {
+          <a href=href.clone() target="_blank">
+            open
+          </a>
+          <a href=href download="">
+            download
+          </a>
+        }
+    });
+
+    let message = part.message.map(|message| {
+        view! {
+          <A href=format!("/{mailbox}/{message}")>
+            <span>view</span>
+          </A>
+        }
+    });
+
+    let children = (!part.children.is_empty()).then(|| {
+        let children = part
+            .children
+            .into_iter()
+            .map(|child| part_view(child, mailbox.clone(), base.clone()))
+            .collect_view();
+
+        view! { <ul>{children}</ul> }
+    });
+
+    view! {
+      <li>
+        <div class="part">
+          <span class="part-id">{format!("#{}", part.id)}</span>
+          <b>{part.content_type}</b>
+          <span class="part-details">{details}</span>
+          {links}
+          {message}
+        </div>
+        {children}
+      </li>
+    }
+    .into_view()
 }
 
 #[component]
@@ -335,6 +1275,17 @@ fn Mail() -> impl IntoView {
         .expect("to have found the set_mail provided")
         .0;
 
+    let change_event = use_context::<StoreEvent>()
+        .expect("to have found the change_event provided")
+        .0;
+
+    let navigate = store_value(use_navigate());
+
+    // name of the attachment shown in the preview
+    let (preview, set_preview) = create_signal(Option::<String>::None);
+    // opening a mail marks it as read
+    let (mail_read, set_mail_read) = create_signal(true);
+
     let data = create_resource(
         move || {
             (
@@ -347,6 +1298,8 @@ fn Mail() -> impl IntoView {
                 None
             } else {
                 set_mail.update(|value| *value = Some(mail.clone()));
+                set_preview.set(None);
+                set_mail_read.set(true);
                 Some((
                     api::get_mail(mailbox.clone(), mail.clone()).await,
                     mailbox,
@@ -356,6 +1309,41 @@ fn Mail() -> impl IntoView {
         },
     );
 
+    refetch_on_resync(data);
+
+    create_effect(move |_| {
+        let item = match change_event.get() {
+            Some(item) => item,
+            None => return,
+        };
+
+        let (mailbox, mail) = params.with_untracked(|q| {
+            (
+                q.get("mailbox").cloned().unwrap_or_default(),
+                q.get("mail").cloned().unwrap_or_default(),
+            )
+        });
+
+        if item.mailbox().is_some() && item.mailbox() != Some(mailbox.as_str()) {
+            return;
+        }
+
+        // embedded messages are deleted with their mail
+        let root = mail.split('.').next().unwrap_or_default();
+
+        match item {
+            QueueItem::MailRead { mails, .. } if mails.contains(&mail) => set_mail_read.set(true),
+            QueueItem::MailUnread { mails, .. } if mails.contains(&mail) => {
+                set_mail_read.set(false)
+            }
+            QueueItem::MailDeleted { mails, .. } if mails.iter().any(|id| id == root) => {
+                data.refetch()
+            }
+            QueueItem::MailboxRemoved { .. } | QueueItem::StorePurged => data.refetch(),
+            _ => (),
+        }
+    });
+
     let content = move || {
         let ty = params
             .with(|val| val.get("ty").map(|ty| ty.to_lowercase()))
@@ -378,12 +1366,12 @@ fn Mail() -> impl IntoView {
 
                 let content = match ty.as_str() {
                     "html" => {
-                        if let Some(_) = data.html {
+                        if data.html.is_some() {
                             view! {
                               <object
                                 class="content-html"
                                 type="text/html"
-                                data=format!("/data/{mailbox}/{mail}/body.html")
+                                data=format!("/data/{mailbox}/{}/body.html", api::mail_path(&mail))
                               ></object>
                             } .into_view()
                         } else {
@@ -404,6 +1392,49 @@ fn Mail() -> impl IntoView {
                             empty()
                         }
                     }
+                    "headers" => {
+                        if data.headers.is_empty() {
+                            empty()
+                        } else {
+                            view! {
+                              <table class="content-headers">
+                                {data
+                                    .headers
+                                    .into_iter()
+                                    .map(|header| {
+                                        view! {
+                                          <tr>
+                                            <th>{header.name}</th>
+                                            <td>{header.value}</td>
+                                          </tr>
+                                        }
+                                    })
+                                    .collect_view()}
+                              </table>
+                            }
+                            .into_view()
+                        }
+                    }
+                    "structure" => {
+                        if let Some(part) = data.structure {
+                            view! {
+                              <ul class="content-structure">
+                                {part_view(
+                                    part,
+                                    mailbox.clone(),
+                                    format!(
+                                        "/data/{}/{}",
+                                        api::encode_path_segment(&mailbox),
+                                        api::mail_path(&mail)
+                                    ),
+                                )}
+                              </ul>
+                            }
+                            .into_view()
+                        } else {
+                            empty()
+                        }
+                    }
                     _ => view! {
                       <div>
                         <p>Unknown type</p>
@@ -413,28 +1444,206 @@ fn Mail() -> impl IntoView {
                 };
 
                 let from = data.metadata.from;
+                let to = api::display_addresses(&data.metadata.to);
+                let cc = (!data.metadata.cc.is_empty()).then(|| {
+                    let cc = api::display_addresses(&data.metadata.cc);
+                    view! {
+                      <p>
+                        <span>
+                          <b>Cc</b>
+                          :
+                          {" "}
+                        </span>
+                        {cc}
+                      </p>
+                    }
+                });
                 let subject = data.metadata.subject;
+                let warnings = (!data.metadata.warnings.is_empty()).then(|| {
+                    view! {
+                      <ul class="warnings box">
+                        {data
+                            .metadata
+                            .warnings
+                            .into_iter()
+                            .map(|warning| view! { <li>{warning}</li> })
+                            .collect_view()}
+                      </ul>
+                    }
+                });
+
+                let attachment_preview = {
+                    let attachments = data.attachments.clone();
+                    let mailbox = mailbox.clone();
+                    let mail = mail.clone();
+
+                    move || {
+                        preview
+                            .get()
+                            .and_then(|name| attachments.iter().find(|a| a.name == name).cloned())
+                            .map(|attachment| {
+                                preview_view(attachment, &mailbox, &mail, set_preview)
+                            })
+                    }
+                };
+
+                let downloads = {
+                    let zip = (!data.attachments.is_empty()).then(|| {
+                        view! {
+                          <a href=format!("/download/{mailbox}/{mail}/attachments.zip") download="">
+                            attachments as .zip
+                          </a>
+                        }
+                    });
+
+                    view! {
+                      <p>
+                        <span>
+                          <b>Download</b>
+                          :
+                          {" "}
+                        </span>
+                        <a href=format!("/download/{mailbox}/{mail}/message.eml") download="">
+                          original as .eml
+                        </a>
+                        {zip}
+                      </p>
+                    }
+                };
+
+                let actions = {
+                    let toggle = {
+                        let mailbox = mailbox.clone();
+                        let mail = mail.clone();
+
+                        move |_| {
+                            let mailbox = mailbox.clone();
+                            let mail = mail.clone();
+                            let read = !mail_read.get_untracked();
+
+                            spawn_local(async move {
+                                match api::set_read(mailbox, mail, read).await {
+                                    Ok(_) => set_mail_read.set(read),
+                                    Err(err) => {
+                                        leptos::logging::error!(
+                                            "failed to change read state: {}",
+                                            err
+                                        )
+                                    }
+                                }
+                            });
+                        }
+                    };
+
+                    // embedded messages are part of their mail
+                    let delete = data.parent.is_none().then(|| {
+                        let mailbox = mailbox.clone();
+                        let mail = mail.clone();
+
+                        let delete = move |_| {
+                            let mailbox = mailbox.clone();
+                            let mail = mail.clone();
+
+                            if confirm("Delete this mail?") {
+                                spawn_local(async move {
+                                    match api::delete_mail(mailbox.clone(), mail).await {
+                                        Ok(_) => navigate.with_value(|navigate| {
+                                            navigate(&format!("/{mailbox}"), Default::default())
+                                        }),
+                                        Err(err) => {
+                                            leptos::logging::error!(
+                                                "failed to delete mail: {}",
+                                                err
+                                            )
+                                        }
+                                    }
+                                });
+                            }
+                        };
+
+                        view! {
+                          <button class="link" on:click=delete>
+                            delete
+                          </button>
+                        }
+                    });
+
+                    view! {
+                      <p class="actions">
+                        <span>
+                          <b>Actions</b>
+                          :
+                          {" "}
+                        </span>
+                        <button class="link" on:click=toggle>
+                          {move || if mail_read.get() { "mark as unread" } else { "mark as read" }}
+                        </button>
+                        {delete}
+                      </p>
+                    }
+                };
 
                 let attachments = if data.attachments.is_empty() {
                     view! { <i>none</i> }
                     .into_view()
                 } else {
-                    data.attachments
+                    let path = api::mail_path(&mail);
+
+                    view! {
+                      <ul class="attachments">
+                        {data
+                            .attachments
+                            .into_iter()
+                            .map(|entry| attachment_view(entry, &mailbox, &path, set_preview))
+                            .collect_view()}
+                      </ul>
+                    }
+                    .into_view()
+                };
+
+                let messages = (!data.messages.is_empty()).then(|| {
+                    let links = data
+                        .messages
                         .into_iter()
                         .map(|entry| {
                             view! {
-                              <a
-                                href=format!("/data/{mailbox}/{mail}/attachments/{entry}")
-                                target="_blank"
-                              >
-                                <span>{entry}</span>
-                              </a>
+                              <A href=format!("/{mailbox}/{}", entry.id)>
+                                <span>{entry.subject}</span>
+                              </A>
                             }
                         })
-                        .collect_view()
-                };
+                        .collect_view();
+
+                    view! {
+                      <p>
+                        <span>
+                          <b>Messages</b>
+                          :
+                          {" "}
+                        </span>
+                        {links}
+                      </p>
+                    }
+                });
+
+                let parent = data.parent.map(|parent| {
+                    view! {
+                      <p>
+                        <span>
+                          <b>Embedded in</b>
+                          :
+                          {" "}
+                        </span>
+                        <A href=format!("/{mailbox}/{parent}")>
+                          <span>parent mail</span>
+                        </A>
+                      </p>
+                    }
+                });
 
-                let selectables = ["HTML", "Text", "Raw"]
+                let calendars = data.calendars.into_iter().map(calendar_view).collect_view();
+
+                let selectables = ["HTML", "Text", "Raw", "Headers", "Structure"]
                     .into_iter()
                     .map(|v| (v, v.to_lowercase()))
                     .collect::<Vec<_>>();
@@ -450,6 +1659,15 @@ fn Mail() -> impl IntoView {
                         </span>
                         {from}
                       </p>
+                      <p>
+                        <span>
+                          <b>To</b>
+                          :
+                          {" "}
+                        </span>
+                        {to}
+                      </p>
+                      {cc}
                       <p>
                         <span>
                           <b>Subject</b>
@@ -458,15 +1676,22 @@ fn Mail() -> impl IntoView {
                         </span>
                         {subject}
                       </p>
-                      <p>
+                      <div>
                         <span>
                           <b>Attachments</b>
                           :
                           {" "}
                         </span>
                         {attachments}
-                      </p>
+                      </div>
+                      {downloads}
+                      {actions}
+                      {messages}
+                      {parent}
                     </div>
+                    {warnings}
+                    {calendars}
+                    {attachment_preview}
                     <div class="selectable box">
                       {selectables
                           .into_iter()
@@ -503,8 +1728,9 @@ fn Mail() -> impl IntoView {
 pub fn App() -> impl IntoView {
     provide_meta_context();
 
-    let change_event = sse_events();
+    let (change_event, resync) = sse_events();
     provide_context(change_event);
+    provide_context(resync);
 
     view! {
       <>
@@ -0,0 +1,507 @@
+//! Parser for iCalendar (RFC 5545) objects sent as invitations.
+//!
+//! Only the properties relevant for previewing an invitation are
+//! extracted. Everything violating the structure of the format is
+//! collected as a problem instead of failing the whole calendar.
+
+use crate::api::{Attendee, Calendar, CalendarEvent, CalendarTime};
+
+/// Content line of a calendar or vCard object (`NAME;PARAM=VALUE:value`).
+pub(crate) struct Line {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl Line {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Joins folded lines, i.e. lines starting with a space or tab continue
+/// the previous line.
+pub(crate) fn unfold(input: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in input.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    lines.retain(|line| !line.trim().is_empty());
+    lines
+}
+
+pub(crate) fn parse_line(line: &str) -> Option<Line> {
+    let mut in_quotes = false;
+    let mut colon = None;
+
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ':' if !in_quotes => {
+                colon = Some(idx);
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    let (head, value) = line.split_at(colon?);
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+
+    for (idx, c) in head.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                segments.push(&head[start..idx]);
+                start = idx + 1;
+            }
+            _ => (),
+        }
+    }
+    segments.push(&head[start..]);
+
+    let name = segments[0].trim().to_uppercase();
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let params = segments[1..]
+        .iter()
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| {
+            (
+                key.trim().to_uppercase(),
+                value.trim_matches('"').to_string(),
+            )
+        })
+        .collect();
+
+    Some(Line {
+        name,
+        params,
+        value: value[1..].to_string(),
+    })
+}
+
+/// Resolves the escape sequences of TEXT values.
+pub(crate) fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => out.push('\n'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+fn strip_mailto(value: &str) -> String {
+    match value.get(..7) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => value[7..].to_string(),
+        _ => value.to_string(),
+    }
+}
+
+fn attendee(line: &Line) -> Attendee {
+    Attendee {
+        name: line.param("CN").map(|v| v.to_string()),
+        address: Some(strip_mailto(&line.value)).filter(|v| !v.is_empty()),
+        role: line.param("ROLE").map(|v| v.to_uppercase()),
+        status: line.param("PARTSTAT").map(|v| v.to_uppercase()),
+        rsvp: line
+            .param("RSVP")
+            .map(|v| v.eq_ignore_ascii_case("TRUE"))
+            .unwrap_or(false),
+    }
+}
+
+/// Checks whether the value is a DATE (`YYYYMMDD`) or DATE-TIME
+/// (`YYYYMMDDTHHMMSS[Z]`).
+fn is_valid_time(value: &str, all_day: bool) -> bool {
+    let digits = |v: &str| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit());
+
+    if all_day {
+        return value.len() == 8 && digits(value);
+    }
+
+    let value = value.strip_suffix('Z').unwrap_or(value);
+
+    match value.split_once('T') {
+        Some((date, time)) => date.len() == 8 && digits(date) && time.len() == 6 && digits(time),
+        None => false,
+    }
+}
+
+fn time(line: &Line, problems: &mut Vec<String>) -> CalendarTime {
+    let all_day = line
+        .param("VALUE")
+        .map(|v| v.eq_ignore_ascii_case("DATE"))
+        .unwrap_or(false)
+        || line.value.len() == 8;
+
+    if !is_valid_time(&line.value, all_day) {
+        problems.push(format!(
+            "{} has an invalid value `{}`",
+            line.name, line.value
+        ));
+    }
+
+    CalendarTime {
+        value: line.value.clone(),
+        timezone: line
+            .param("TZID")
+            .map(|v| v.to_string())
+            .or_else(|| line.value.ends_with('Z').then(|| String::from("UTC"))),
+        all_day,
+    }
+}
+
+/// Parses all calendars contained in the text.
+pub fn parse(part: usize, input: &str) -> Calendar {
+    let mut calendar = Calendar {
+        part,
+        method: None,
+        timezones: Vec::new(),
+        events: Vec::new(),
+        problems: Vec::new(),
+    };
+
+    let mut stack: Vec<String> = Vec::new();
+    // events are validated once all timezones are known, VTIMEZONE may
+    // follow the events using it
+    let mut events: Vec<(CalendarEvent, bool)> = Vec::new();
+    let mut event: Option<CalendarEvent> = None;
+    let mut has_duration = false;
+    let mut has_calendar = false;
+
+    for (idx, raw) in unfold(input).iter().enumerate() {
+        let line = match parse_line(raw) {
+            Some(line) => line,
+            None => {
+                calendar.problems.push(format!(
+                    "line {} is not a valid content line: `{}`",
+                    idx + 1,
+                    raw
+                ));
+                continue;
+            }
+        };
+
+        match line.name.as_str() {
+            "BEGIN" => {
+                let component = line.value.trim().to_uppercase();
+
+                if stack.is_empty() && component != "VCALENDAR" {
+                    calendar
+                        .problems
+                        .push(format!("{} outside of VCALENDAR", component));
+                }
+
+                match component.as_str() {
+                    "VCALENDAR" => has_calendar = true,
+                    "VEVENT" => {
+                        event = Some(CalendarEvent::default());
+                        has_duration = false;
+                    }
+                    _ => (),
+                }
+
+                stack.push(component);
+            }
+            "END" => {
+                let component = line.value.trim().to_uppercase();
+
+                match stack.pop() {
+                    Some(open) if open == component => (),
+                    Some(open) => calendar
+                        .problems
+                        .push(format!("END:{} does not match BEGIN:{}", component, open)),
+                    None => calendar
+                        .problems
+                        .push(format!("END:{} without BEGIN", component)),
+                }
+
+                if component == "VEVENT" {
+                    if let Some(event) = event.take() {
+                        events.push((event, has_duration));
+                    }
+                }
+            }
+            name => {
+                let component = stack.last().map(|v| v.as_str());
+
+                match (component, name) {
+                    (Some("VCALENDAR"), "METHOD") => {
+                        calendar.method = Some(line.value.trim().to_uppercase())
+                    }
+                    (Some("VTIMEZONE"), "TZID") => calendar.timezones.push(line.value.clone()),
+                    (Some("VEVENT"), _) => {
+                        // the event is gone if its END did not match
+                        let event = match event.as_mut() {
+                            Some(event) => event,
+                            None => continue,
+                        };
+
+                        match name {
+                            "UID" => event.uid = Some(line.value.clone()),
+                            "SUMMARY" => event.summary = Some(unescape(&line.value)),
+                            "DESCRIPTION" => event.description = Some(unescape(&line.value)),
+                            "LOCATION" => event.location = Some(unescape(&line.value)),
+                            "STATUS" => event.status = Some(line.value.to_uppercase()),
+                            "SEQUENCE" => event.sequence = line.value.trim().parse().ok(),
+                            "RRULE" => event.recurrence = Some(line.value.clone()),
+                            "ORGANIZER" => event.organizer = Some(attendee(&line)),
+                            "ATTENDEE" => event.attendees.push(attendee(&line)),
+                            "DTSTART" => event.start = Some(time(&line, &mut event.problems)),
+                            "DTEND" => event.end = Some(time(&line, &mut event.problems)),
+                            "DURATION" => has_duration = true,
+                            _ => (),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    if !has_calendar {
+        calendar.problems.push(String::from("missing VCALENDAR"));
+    }
+
+    for component in stack {
+        calendar
+            .problems
+            .push(format!("BEGIN:{} is never closed", component));
+    }
+
+    if let Some(event) = event {
+        events.push((event, has_duration));
+    }
+
+    for (mut event, has_duration) in events {
+        validate(&mut event, has_duration, &calendar);
+        calendar.events.push(event);
+    }
+
+    if has_calendar && calendar.events.is_empty() {
+        calendar
+            .problems
+            .push(String::from("calendar contains no events"));
+    }
+
+    calendar
+}
+
+fn validate(event: &mut CalendarEvent, has_duration: bool, calendar: &Calendar) {
+    if event.uid.is_none() {
+        event.problems.push(String::from("missing UID"));
+    }
+
+    if event.start.is_none() {
+        event.problems.push(String::from("missing DTSTART"));
+    }
+
+    if event.end.is_some() && has_duration {
+        event
+            .problems
+            .push(String::from("DTEND and DURATION must not occur together"));
+    }
+
+    if let (Some(start), Some(end)) = (&event.start, &event.end) {
+        // values in the same format and timezone are ordered lexically
+        if start.timezone == end.timezone
+            && start.all_day == end.all_day
+            && start.value.len() == end.value.len()
+            && end.value < start.value
+        {
+            event.problems.push(String::from("DTEND is before DTSTART"));
+        }
+    }
+
+    let mut undefined: Vec<&String> = Vec::new();
+
+    for timezone in [&event.start, &event.end]
+        .into_iter()
+        .flatten()
+        .filter_map(|time| time.timezone.as_ref())
+    {
+        if timezone != "UTC"
+            && !calendar.timezones.contains(timezone)
+            && !undefined.contains(&timezone)
+        {
+            undefined.push(timezone);
+        }
+    }
+
+    for timezone in undefined {
+        event.problems.push(format!(
+            "timezone `{}` is not defined by a VTIMEZONE",
+            timezone
+        ));
+    }
+
+    if calendar.method.as_deref() == Some("REQUEST") && event.organizer.is_none() {
+        event
+            .problems
+            .push(String::from("METHOD:REQUEST requires an ORGANIZER"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVITE: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+METHOD:REQUEST\r
+BEGIN:VEVENT\r
+UID:1234@example.com\r
+SUMMARY:Planning\\, part 1\r
+DTSTART;TZID=Europe/Berlin:20230904T100000\r
+DTEND;TZID=Europe/Berlin:20230904T110000\r
+ORGANIZER;CN=Jane:mailto:jane@example.com\r
+ATTENDEE;CN=John;PARTSTAT=NEEDS-ACTION;RSVP=TRUE:MAILTO:john@example.com\r
+END:VEVENT\r
+BEGIN:VTIMEZONE\r
+TZID:Europe/Berlin\r
+END:VTIMEZONE\r
+END:VCALENDAR\r
+";
+
+    fn parse_event(lines: &str) -> CalendarEvent {
+        let input = format!(
+            "BEGIN:VCALENDAR\nBEGIN:VEVENT\n{}\nEND:VEVENT\nEND:VCALENDAR\n",
+            lines
+        );
+        let mut calendar = parse(1, &input);
+
+        assert!(calendar.problems.is_empty(), "{:?}", calendar.problems);
+        calendar.events.remove(0)
+    }
+
+    #[test]
+    fn parses_invitation() {
+        let calendar = parse(2, INVITE);
+
+        assert_eq!(calendar.part, 2);
+        assert_eq!(calendar.method.as_deref(), Some("REQUEST"));
+        assert_eq!(calendar.timezones, vec!["Europe/Berlin"]);
+        assert!(calendar.problems.is_empty(), "{:?}", calendar.problems);
+
+        let event = &calendar.events[0];
+        assert_eq!(event.summary.as_deref(), Some("Planning, part 1"));
+        assert_eq!(
+            event.start.as_ref().unwrap().timezone.as_deref(),
+            Some("Europe/Berlin")
+        );
+        assert_eq!(
+            event.organizer.as_ref().unwrap().address.as_deref(),
+            Some("jane@example.com")
+        );
+
+        let attendee = &event.attendees[0];
+        assert_eq!(attendee.name.as_deref(), Some("John"));
+        assert_eq!(attendee.address.as_deref(), Some("john@example.com"));
+        assert_eq!(attendee.status.as_deref(), Some("NEEDS-ACTION"));
+        assert!(attendee.rsvp);
+
+        // the timezone is defined after the event using it
+        assert!(event.problems.is_empty(), "{:?}", event.problems);
+    }
+
+    #[test]
+    fn unfolds_lines() {
+        let event = parse_event("UID:1\nDTSTART:20230904\nDESCRIPTION:first\n  second\n\t third");
+
+        assert_eq!(event.description.as_deref(), Some("first second third"));
+        assert!(event.start.unwrap().all_day);
+    }
+
+    #[test]
+    fn keeps_multibyte_addresses() {
+        let event =
+            parse_event("UID:1\nDTSTART:20230904\nATTENDEE:jürgen@example.com\nATTENDEE:mailtö:x");
+
+        assert_eq!(
+            event.attendees[0].address.as_deref(),
+            Some("jürgen@example.com")
+        );
+        assert_eq!(event.attendees[1].address.as_deref(), Some("mailtö:x"));
+    }
+
+    #[test]
+    fn reports_invalid_events() {
+        let event = parse_event("DTSTART:20230904T100000Z\nDTEND:20230904T090000Z\nDURATION:PT1H");
+        assert_eq!(
+            event.problems,
+            vec![
+                "missing UID",
+                "DTEND and DURATION must not occur together",
+                "DTEND is before DTSTART",
+            ]
+        );
+
+        let event = parse_event("UID:1\nDTEND:2023-09-04");
+        assert_eq!(
+            event.problems,
+            vec!["DTEND has an invalid value `2023-09-04`", "missing DTSTART"]
+        );
+    }
+
+    #[test]
+    fn reports_undefined_timezones() {
+        let event = parse_event(
+            "UID:1\nDTSTART;TZID=Mars/Olympus:20230904T100000\nDTEND;TZID=Mars/Olympus:20230904T110000",
+        );
+
+        assert_eq!(
+            event.problems,
+            vec!["timezone `Mars/Olympus` is not defined by a VTIMEZONE"]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_structure() {
+        let calendar = parse(
+            1,
+            "BEGIN:VCALENDAR\nMETHOD:REQUEST\nBEGIN:VEVENT\nUID:1\nDTSTART:20230904\nnonsense\nEND:VTODO\n",
+        );
+        assert_eq!(
+            calendar.problems,
+            vec![
+                "line 6 is not a valid content line: `nonsense`",
+                "END:VTODO does not match BEGIN:VEVENT",
+                "BEGIN:VCALENDAR is never closed",
+            ]
+        );
+        assert_eq!(
+            calendar.events[0].problems,
+            vec!["METHOD:REQUEST requires an ORGANIZER"]
+        );
+
+        let calendar = parse(1, "BEGIN:VEVENT\nEND:VEVENT\n");
+        assert_eq!(
+            calendar.problems,
+            vec!["VEVENT outside of VCALENDAR", "missing VCALENDAR"]
+        );
+    }
+}
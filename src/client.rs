@@ -0,0 +1,501 @@
+//! Client of the REST API for the integration tests of services sending
+//! mails, enabled by the `client` feature.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), mail_blackhole::client::ClientError> {
+//! use mail_blackhole::api::WaitQuery;
+//! use mail_blackhole::client::Client;
+//!
+//! let client = Client::new("http://localhost:8080");
+//!
+//! // trigger the sign-up of the service under test, then
+//! let found = client
+//!     .wait_for_mail(&WaitQuery {
+//!         mailbox: Some("user@example.com".into()),
+//!         subject: Some("confirm".into()),
+//!         ..Default::default()
+//!     })
+//!     .await?
+//!     .expect("no confirmation mail");
+//!
+//! let link = client
+//!     .assert_single_mail_with_link(&found.mailbox, "/confirm")
+//!     .await;
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+
+use crate::api::{
+    encode_path_segment, ErrorBody, Mail, Mailbox, MailboxItem, MailboxPage, MailboxQuery,
+    WaitQuery, WaitedMail, MAX_PAGE_SIZE,
+};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    /// The server rejected the request.
+    Api(ErrorBody),
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Http(err) => Some(err),
+            ClientError::Api(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ClientError::Http(err) => write!(f, "{}", err),
+            ClientError::Api(body) => write!(f, "{} {}", body.status, body.error),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Http(err)
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base: String,
+}
+
+impl Client {
+    /// Creates a client for the server at `base`, e.g.
+    /// `http://localhost:8080`.
+    pub fn new(base: impl Into<String>) -> Self {
+        Self::with_client(base, reqwest::Client::new())
+    }
+
+    /// Creates a client sending its requests with `http`, e.g. to set a
+    /// timeout.
+    pub fn with_client(base: impl Into<String>, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            base: base.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let path = segments
+            .iter()
+            .map(|segment| encode_path_segment(segment))
+            .collect::<Vec<_>>()
+            .join("/");
+
+        self.http
+            .request(method, format!("{}/api/v1/{}", self.base, path))
+    }
+
+    /// Sends the request. Returns `None` if the server answered with
+    /// `404 Not Found` or `408 Request Timeout`.
+    async fn send(&self, request: RequestBuilder) -> Result<Option<Response>, ClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+
+        if status == StatusCode::NOT_FOUND || status == StatusCode::REQUEST_TIMEOUT {
+            return Ok(None);
+        }
+
+        if status.is_success() {
+            return Ok(Some(response));
+        }
+
+        let text = response.text().await?;
+
+        Err(ClientError::Api(serde_json::from_str(&text).unwrap_or(
+            ErrorBody {
+                status: status.as_u16(),
+                error: text,
+            },
+        )))
+    }
+
+    async fn json<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<Option<T>, ClientError> {
+        match self.send(request).await? {
+            Some(response) => Ok(Some(response.json().await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn bytes(&self, request: RequestBuilder) -> Result<Option<Vec<u8>>, ClientError> {
+        match self.send(request).await? {
+            Some(response) => Ok(Some(response.bytes().await?.to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    /// Applies a change. Returns false if the mailbox or mail does not
+    /// exist.
+    async fn change(&self, method: Method, segments: &[&str]) -> Result<bool, ClientError> {
+        Ok(self.send(self.request(method, segments)).await?.is_some())
+    }
+
+    pub async fn mailboxes(&self) -> Result<Vec<Mailbox>, ClientError> {
+        self.json(self.request(Method::GET, &["mailboxes"]))
+            .await
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Lists a page of the mails of a mailbox matching the query. Returns
+    /// `None` if the mailbox does not exist.
+    pub async fn page(
+        &self,
+        mailbox: &str,
+        query: &MailboxQuery,
+        cursor: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Option<MailboxPage>, ClientError> {
+        let mut request = self
+            .request(Method::GET, &["mailboxes", mailbox, "mails"])
+            .query(query);
+
+        if let Some(cursor) = cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+
+        self.json(request).await
+    }
+
+    /// Lists all mails of a mailbox matching the query, an unknown mailbox
+    /// has no mails.
+    pub async fn mails(
+        &self,
+        mailbox: &str,
+        query: &MailboxQuery,
+    ) -> Result<Vec<MailboxItem>, ClientError> {
+        let mut items = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let page = match self
+                .page(mailbox, query, cursor.as_deref(), Some(MAX_PAGE_SIZE))
+                .await?
+            {
+                Some(page) => page,
+                None => return Ok(items),
+            };
+
+            items.extend(page.items);
+
+            match page.next {
+                Some(next) => cursor = Some(next),
+                None => return Ok(items),
+            }
+        }
+    }
+
+    /// Reads a mail without marking it as read.
+    pub async fn mail(&self, mailbox: &str, mail: &str) -> Result<Option<Mail>, ClientError> {
+        self.json(self.request(Method::GET, &["mailboxes", mailbox, "mails", mail]))
+            .await
+    }
+
+    /// Original message as received.
+    pub async fn raw(&self, mailbox: &str, mail: &str) -> Result<Option<Vec<u8>>, ClientError> {
+        self.bytes(self.request(Method::GET, &["mailboxes", mailbox, "mails", mail, "raw"]))
+            .await
+    }
+
+    pub async fn attachment(
+        &self,
+        mailbox: &str,
+        mail: &str,
+        name: &str,
+    ) -> Result<Option<Vec<u8>>, ClientError> {
+        self.bytes(self.request(
+            Method::GET,
+            &["mailboxes", mailbox, "mails", mail, "attachments", name],
+        ))
+        .await
+    }
+
+    pub async fn set_read(
+        &self,
+        mailbox: &str,
+        mail: &str,
+        read: bool,
+    ) -> Result<bool, ClientError> {
+        let method = if read { Method::PUT } else { Method::DELETE };
+
+        self.change(method, &["mailboxes", mailbox, "mails", mail, "read"])
+            .await
+    }
+
+    pub async fn delete_mail(&self, mailbox: &str, mail: &str) -> Result<bool, ClientError> {
+        self.change(Method::DELETE, &["mailboxes", mailbox, "mails", mail])
+            .await
+    }
+
+    /// Deletes all mails of a mailbox but keeps the mailbox.
+    pub async fn empty_mailbox(&self, mailbox: &str) -> Result<bool, ClientError> {
+        self.change(Method::DELETE, &["mailboxes", mailbox, "mails"])
+            .await
+    }
+
+    pub async fn delete_mailbox(&self, mailbox: &str) -> Result<bool, ClientError> {
+        self.change(Method::DELETE, &["mailboxes", mailbox]).await
+    }
+
+    /// Deletes all mailboxes, e.g. before each test.
+    pub async fn purge(&self) -> Result<(), ClientError> {
+        self.change(Method::DELETE, &["mailboxes"])
+            .await
+            .map(|_| ())
+    }
+
+    /// Waits until a mail matching the query is stored. Returns `None` if
+    /// no mail arrived within the timeout of the query.
+    pub async fn wait_for_mail(
+        &self,
+        query: &WaitQuery,
+    ) -> Result<Option<WaitedMail>, ClientError> {
+        self.json(self.http.get(format!("{}/wait", self.base)).query(query))
+            .await
+    }
+
+    /// Returns the only mail of the mailbox matching the query.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no or more than one such mail or the request
+    /// fails.
+    pub async fn assert_single_mail(&self, mailbox: &str, query: &MailboxQuery) -> Mail {
+        let items = self
+            .mails(mailbox, query)
+            .await
+            .unwrap_or_else(|err| panic!("listing the mails to `{}` failed: {}", mailbox, err));
+
+        let item = match items.as_slice() {
+            [item] => item,
+            _ => panic!(
+                "expected exactly one mail to `{}`, found {}: {:?}",
+                mailbox,
+                items.len(),
+                items.iter().map(|item| &item.subject).collect::<Vec<_>>()
+            ),
+        };
+
+        self.mail(mailbox, &item.id)
+            .await
+            .unwrap_or_else(|err| panic!("reading mail `{}` failed: {}", item.id, err))
+            .unwrap_or_else(|| panic!("mail `{}` was deleted", item.id))
+    }
+
+    /// Asserts that no mail of the mailbox matches the query.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is such a mail or the request fails.
+    pub async fn assert_no_mail(&self, mailbox: &str, query: &MailboxQuery) {
+        let items = self
+            .mails(mailbox, query)
+            .await
+            .unwrap_or_else(|err| panic!("listing the mails to `{}` failed: {}", mailbox, err));
+
+        if !items.is_empty() {
+            panic!(
+                "expected no mail to `{}`, found {}: {:?}",
+                mailbox,
+                items.len(),
+                items.iter().map(|item| &item.subject).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    /// Returns the link containing `pattern` of the only mail of the
+    /// mailbox, see [`Mail::assert_single_link`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mailbox has not exactly one mail or the mail has not
+    /// exactly one such link.
+    pub async fn assert_single_mail_with_link(&self, mailbox: &str, pattern: &str) -> String {
+        self.assert_single_mail(mailbox, &MailboxQuery::default())
+            .await
+            .assert_single_link(pattern)
+    }
+}
+
+impl Mail {
+    /// Links (http and https URLs) found in the text and HTML body, in
+    /// order of their first appearance.
+    pub fn links(&self) -> Vec<String> {
+        let mut links: Vec<String> = Vec::new();
+
+        for body in [&self.text, &self.html].into_iter().flatten() {
+            let mut rest = body.as_str();
+
+            while let Some(start) = ["http://", "https://"]
+                .iter()
+                .filter_map(|scheme| rest.find(scheme))
+                .min()
+            {
+                let candidate = &rest[start..];
+                let end = candidate
+                    .find(|c: char| c.is_whitespace() || "\"'<>`".contains(c))
+                    .unwrap_or(candidate.len());
+                let mut link = candidate[..end].trim_end_matches(['.', ',', ';', ':', '!', '?']);
+
+                if link.ends_with(')') && !link.contains('(') {
+                    link = &link[..link.len() - 1];
+                }
+
+                let link = link.replace("&amp;", "&");
+                if !links.contains(&link) {
+                    links.push(link);
+                }
+
+                rest = &candidate[end..];
+            }
+        }
+
+        links
+    }
+
+    /// Returns the only link containing `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no or more than one such link.
+    pub fn assert_single_link(&self, pattern: &str) -> String {
+        let mut links = self
+            .links()
+            .into_iter()
+            .filter(|link| link.contains(pattern))
+            .collect::<Vec<_>>();
+
+        if links.len() != 1 {
+            panic!(
+                "expected exactly one link containing `{}` in mail `{}`, found {}: {:?}",
+                pattern,
+                self.metadata.subject,
+                links.len(),
+                links
+            );
+        }
+
+        links.remove(0)
+    }
+
+    /// Whether the text or HTML body contains `needle`.
+    pub fn body_contains(&self, needle: &str) -> bool {
+        [&self.text, &self.html]
+            .into_iter()
+            .flatten()
+            .any(|body| body.contains(needle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Metadata;
+
+    fn mail(text: Option<&str>, html: Option<&str>) -> Mail {
+        Mail {
+            html: html.map(|v| v.to_string()),
+            text: text.map(|v| v.to_string()),
+            raw: None,
+            attachments: Vec::new(),
+            metadata: Metadata {
+                id: String::from("1"),
+                subject: String::from("Welcome"),
+                from: String::from("service@example.com"),
+                date: None,
+                to: Vec::new(),
+                cc: Vec::new(),
+                reply_to: Vec::new(),
+                message_id: None,
+                in_reply_to: Vec::new(),
+                references: Vec::new(),
+                list: Vec::new(),
+                charsets: Vec::new(),
+                warnings: Vec::new(),
+            },
+            headers: Vec::new(),
+            structure: None,
+            messages: Vec::new(),
+            parent: None,
+            calendars: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn finds_links() {
+        let mail = mail(
+            Some(
+                "Confirm at https://example.com/confirm?token=abc.\n\
+                 (see http://example.com/help) or https://de.wikipedia.org/wiki/Test_(Begriff), \
+                 https://exämple.com/grüße!",
+            ),
+            Some(
+                "<a href=\"https://example.com/confirm?token=abc&amp;lang=en\">confirm</a>\
+                 <a href='https://example.com/confirm?token=abc'>again</a>",
+            ),
+        );
+
+        assert_eq!(
+            mail.links(),
+            vec![
+                "https://example.com/confirm?token=abc",
+                "http://example.com/help",
+                "https://de.wikipedia.org/wiki/Test_(Begriff)",
+                "https://exämple.com/grüße",
+                "https://example.com/confirm?token=abc&lang=en",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_no_links() {
+        assert!(mail(None, None).links().is_empty());
+        assert!(mail(Some("http:/example.com"), None).links().is_empty());
+    }
+
+    #[test]
+    fn asserts_single_link() {
+        let mail = mail(
+            Some("https://example.com/confirm/1 https://example.com/unsubscribe"),
+            Some("<a href=\"https://example.com/confirm/1\">confirm</a>"),
+        );
+
+        assert_eq!(
+            mail.assert_single_link("/confirm"),
+            "https://example.com/confirm/1"
+        );
+        assert!(mail.body_contains("unsubscribe"));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected exactly one link containing `example.com`")]
+    fn rejects_ambiguous_links() {
+        mail(
+            Some("https://example.com/confirm https://example.com/unsubscribe"),
+            None,
+        )
+        .assert_single_link("example.com");
+    }
+
+    #[test]
+    #[should_panic(expected = "found 0")]
+    fn rejects_missing_links() {
+        mail(Some("no links here"), None).assert_single_link("/confirm");
+    }
+}
@@ -0,0 +1,317 @@
+//! Publishing of store events to the connected clients.
+//!
+//! Every event gets a monotonic id and the latest events are kept in a
+//! journal, so clients reconnecting with the id of the last event they
+//! received get the missed events replayed. Ids start at the time the server
+//! started in milliseconds, so they keep increasing across restarts.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use regex::{Regex, RegexBuilder};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::api::domain_of;
+use crate::QueueItem;
+
+/// Number of events kept for replaying.
+pub const JOURNAL_SIZE: usize = 1024;
+/// Number of events a subscriber may fall behind before it lags.
+const CHANNEL_SIZE: usize = 256;
+
+/// Published event with its id.
+#[derive(Debug)]
+pub struct Envelope {
+    pub id: u64,
+    pub item: QueueItem,
+}
+
+/// Events missed by a reconnecting subscriber.
+pub enum Replay {
+    Events(Vec<Arc<Envelope>>),
+    /// The missed events are no longer journaled, the subscriber has to
+    /// reload its state. Contains the id of the latest event.
+    Resync(u64),
+}
+
+struct Journal {
+    next: u64,
+    entries: VecDeque<Arc<Envelope>>,
+}
+
+#[derive(Clone)]
+pub struct Events {
+    sender: Sender<Arc<Envelope>>,
+    journal: Arc<Mutex<Journal>>,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_SIZE);
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        Self {
+            sender,
+            journal: Arc::new(Mutex::new(Journal {
+                next: start.as_millis() as u64,
+                entries: VecDeque::with_capacity(JOURNAL_SIZE),
+            })),
+        }
+    }
+}
+
+impl Events {
+    /// Journals the event and sends it to all subscribers. Returns the id of
+    /// the event.
+    pub fn publish(&self, item: QueueItem) -> u64 {
+        let mut journal = self.journal.lock().unwrap();
+
+        let id = journal.next;
+        let envelope = Arc::new(Envelope { id, item });
+
+        journal.next += 1;
+        if journal.entries.len() == JOURNAL_SIZE {
+            journal.entries.pop_front();
+        }
+        journal.entries.push_back(envelope.clone());
+
+        // sent while holding the lock to keep the order of the journal,
+        // nobody listening is not an error
+        let _ = self.sender.send(envelope);
+
+        id
+    }
+
+    /// Id of the latest published event, or of the one before the first.
+    pub fn latest(&self) -> u64 {
+        self.journal.lock().unwrap().next - 1
+    }
+
+    /// Subscribes to all events after `last`, the id of the last event the
+    /// subscriber received. Without `last` only new events are received.
+    pub fn subscribe(&self, last: Option<u64>) -> (Replay, Receiver<Arc<Envelope>>) {
+        let journal = self.journal.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let latest = journal.next - 1;
+
+        let replay = match last {
+            None => Replay::Events(Vec::new()),
+            Some(last) if last > latest => Replay::Resync(latest),
+            Some(last) => {
+                let oldest = journal
+                    .entries
+                    .front()
+                    .map(|v| v.id)
+                    .unwrap_or(journal.next);
+
+                if last + 1 < oldest {
+                    Replay::Resync(latest)
+                } else {
+                    Replay::Events(
+                        journal
+                            .entries
+                            .iter()
+                            .filter(|v| v.id > last)
+                            .cloned()
+                            .collect(),
+                    )
+                }
+            }
+        };
+
+        (replay, receiver)
+    }
+}
+
+/// Criteria of the events a subscriber is interested in, all given criteria
+/// must match. The sender and the subject only restrict new mails, other
+/// events are only restricted by their mailbox.
+#[derive(
+    Debug, Default, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize, utoipa::IntoParams,
+)]
+#[into_params(parameter_in = Query)]
+#[serde(default)]
+pub struct Subscription {
+    pub mailbox: Option<String>,
+    pub domain: Option<String>,
+    /// Case-insensitive substring of the sender.
+    pub sender: Option<String>,
+    /// Case-insensitive regular expression matching the subject.
+    pub subject: Option<String>,
+}
+
+/// Compiled [`Subscription`].
+pub struct Filter {
+    subscription: Subscription,
+    subject: Option<Regex>,
+}
+
+impl Filter {
+    pub fn new(subscription: Subscription) -> Result<Self, regex::Error> {
+        let subject = subscription
+            .subject
+            .as_ref()
+            .map(|subject| RegexBuilder::new(subject).case_insensitive(true).build())
+            .transpose()?;
+
+        Ok(Self {
+            subscription,
+            subject,
+        })
+    }
+
+    pub fn matches(&self, item: &QueueItem) -> bool {
+        let subscription = &self.subscription;
+
+        // events affecting all mailboxes are always of interest
+        let mailbox = match item.mailbox() {
+            Some(mailbox) => mailbox,
+            None => return true,
+        };
+
+        if subscription
+            .mailbox
+            .as_ref()
+            .map(|v| !v.eq_ignore_ascii_case(mailbox))
+            .unwrap_or(false)
+            || subscription
+                .domain
+                .as_ref()
+                .map(|v| !v.eq_ignore_ascii_case(domain_of(mailbox)))
+                .unwrap_or(false)
+        {
+            return false;
+        }
+
+        match item {
+            QueueItem::MailCreated { mail, from, .. } => {
+                subscription
+                    .sender
+                    .as_ref()
+                    .map(|v| from.to_lowercase().contains(&v.to_lowercase()))
+                    .unwrap_or(true)
+                    && self
+                        .subject
+                        .as_ref()
+                        .map(|v| v.is_match(&mail.subject))
+                        .unwrap_or(true)
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn removed(mailbox: &str) -> QueueItem {
+        QueueItem::MailboxRemoved {
+            mailbox: mailbox.to_string(),
+        }
+    }
+
+    /// Ids of the replayed events, `Err` with the latest id for a resync.
+    fn replayed(events: &Events, last: Option<u64>) -> Result<Vec<u64>, u64> {
+        match events.subscribe(last).0 {
+            Replay::Events(replay) => Ok(replay.iter().map(|v| v.id).collect()),
+            Replay::Resync(latest) => Err(latest),
+        }
+    }
+
+    #[test]
+    fn replays_missed_events() {
+        let events = Events::default();
+        let (_, mut receiver) = events.subscribe(None);
+
+        let first = events.publish(removed("a@b.c"));
+        let second = events.publish(removed("d@b.c"));
+        let third = events.publish(QueueItem::StorePurged);
+
+        assert_eq!(second, first + 1);
+        assert_eq!(third, first + 2);
+        assert_eq!(receiver.try_recv().unwrap().id, first);
+
+        assert_eq!(replayed(&events, None), Ok(vec![]));
+        assert_eq!(replayed(&events, Some(first)), Ok(vec![second, third]));
+        assert_eq!(replayed(&events, Some(third)), Ok(vec![]));
+        // the id of the event before the first one is still complete
+        assert_eq!(
+            replayed(&events, Some(first - 1)),
+            Ok(vec![first, second, third])
+        );
+    }
+
+    #[test]
+    fn resyncs_unknown_events() {
+        let events = Events::default();
+        let first = events.publish(removed("a@b.c"));
+
+        // ids of a previous run or of the future
+        assert_eq!(replayed(&events, Some(first - 2)), Err(first));
+        assert_eq!(replayed(&events, Some(first + 1)), Err(first));
+
+        for _ in 0..JOURNAL_SIZE {
+            events.publish(removed("a@b.c"));
+        }
+        let latest = first + JOURNAL_SIZE as u64;
+
+        // the first event dropped out of the journal
+        assert_eq!(replayed(&events, Some(first - 1)), Err(latest));
+        assert_eq!(replayed(&events, Some(first)).unwrap().len(), JOURNAL_SIZE);
+    }
+
+    #[test]
+    fn filters_events() {
+        let filter = |subscription: Subscription| Filter::new(subscription).unwrap();
+        let created = QueueItem::MailCreated {
+            mailbox: String::from("john@example.com"),
+            mail: crate::api::MailboxItem {
+                subject: String::from("Invoice 42"),
+                id: String::from("1"),
+                read: false,
+                thread: String::from("1"),
+                thread_size: None,
+            },
+            from: String::from("Billing <billing@shop.com>"),
+        };
+
+        assert!(filter(Subscription::default()).matches(&created));
+        assert!(filter(Subscription {
+            domain: Some(String::from("EXAMPLE.com")),
+            sender: Some(String::from("billing@")),
+            subject: Some(String::from("^invoice \\d+$")),
+            ..Default::default()
+        })
+        .matches(&created));
+        assert!(!filter(Subscription {
+            mailbox: Some(String::from("jane@example.com")),
+            ..Default::default()
+        })
+        .matches(&created));
+        assert!(!filter(Subscription {
+            subject: Some(String::from("receipt")),
+            ..Default::default()
+        })
+        .matches(&created));
+
+        // only new mails are restricted by sender and subject
+        let sender = filter(Subscription {
+            sender: Some(String::from("nobody")),
+            ..Default::default()
+        });
+        assert!(sender.matches(&removed("john@example.com")));
+        assert!(filter(Subscription {
+            mailbox: Some(String::from("jane@example.com")),
+            ..Default::default()
+        })
+        .matches(&QueueItem::StorePurged));
+
+        assert!(Filter::new(Subscription {
+            subject: Some(String::from("(")),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
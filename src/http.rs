@@ -1,42 +1,65 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::time::Duration;
 use std::{path::PathBuf, sync::Arc};
 
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::extract::Path;
+use axum::extract::Query;
 use axum::extract::RawQuery;
+use axum::middleware::Next;
 use axum::response::IntoResponse;
 use axum::{
     body::Body as AxumBody,
     extract::State,
-    http::{header::HeaderMap, Request},
+    http::{
+        header::{self, HeaderMap},
+        HeaderValue, Request, Response, StatusCode,
+    },
     response::{
         sse::{Event, KeepAlive},
         Response as AxumResponse, Sse,
     },
     routing::get,
-    Router,
+    Json, Router,
 };
 use futures_util::Stream;
 use leptos::*;
 use leptos_axum::handle_server_fns_with_context;
-use tokio::sync::broadcast::Sender;
+use tokio::sync::broadcast::error::RecvError;
 use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tower_http::set_header::SetResponseHeaderLayer;
 
+use crate::api;
 use crate::app::App;
-use crate::{Args, QueueItem};
+use crate::events::{Envelope, Events, Filter, Replay, Subscription};
+use crate::mail as fs;
+use crate::search::SharedIndex;
+use crate::store::Store;
+use crate::QueueItem;
 
 #[derive(Debug, Clone)]
 struct MailboxesPath(PathBuf);
 
-#[derive(Debug, Clone)]
-struct FilesPath(PathBuf);
-
 #[derive(axum::extract::FromRef, Clone)]
 pub struct Context {
     path: MailboxesPath,
-    sender: Sender<Arc<QueueItem>>,
+    events: Events,
+    index: SharedIndex,
     leptos_options: LeptosOptions,
 }
 
+impl Context {
+    pub(crate) fn store(&self) -> Store {
+        Store {
+            mailboxes: fs::Mailboxes {
+                path: self.path.0.clone(),
+            },
+            index: self.index.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
 async fn leptos_routes_handler(
     State(context): State<Context>,
     req: Request<AxumBody>,
@@ -45,6 +68,8 @@ async fn leptos_routes_handler(
         context.leptos_options.clone(),
         move || {
             provide_context(context.path.0.clone());
+            provide_context(context.index.clone());
+            provide_context(context.events.clone());
         },
         || view! { <App/> },
     );
@@ -64,6 +89,8 @@ async fn server_fn_handler(
         raw_query,
         move || {
             provide_context(context.path.0.clone());
+            provide_context(context.index.clone());
+            provide_context(context.events.clone());
         },
         request,
     )
@@ -100,64 +127,712 @@ async fn static_path(Path(path): Path<String>) -> impl IntoResponse {
     }
 }
 
-pub async fn listen(
-    args: &Args,
-    sender: Sender<Arc<QueueItem>>,
+/// Stored bodies are always UTF-8 encoded, regardless of the charset the
+/// mail declared (e.g. within a HTML meta tag).
+fn utf8_content_type<B>(response: &Response<B>) -> Option<HeaderValue> {
+    let value = response.headers().get(header::CONTENT_TYPE)?;
+    let content_type = value.to_str().ok()?;
+
+    if content_type.starts_with("text/") && !content_type.contains("charset") {
+        HeaderValue::from_str(&format!("{}; charset=utf-8", content_type)).ok()
+    } else {
+        Some(value.clone())
+    }
+}
+
+/// Serves attachments with the content type recorded on delivery instead
+/// of one guessed from the file name and names the file within the
+/// Content-Disposition. Appending `?download` to the URL forces a download
+/// instead of an inline display.
+async fn attachment_headers<B>(
+    State(MailboxesPath(path)): State<MailboxesPath>,
+    request: Request<B>,
+    next: Next<B>,
+) -> AxumResponse {
+    let download = request
+        .uri()
+        .query()
+        .map(|query| query.split('&').any(|v| v == "download"))
+        .unwrap_or(false);
+    let attachment = find_attachment(&path, request.uri().path());
+
+    let mut response = next.run(request).await;
+
+    if let Some(attachment) = attachment.filter(|_| response.status().is_success()) {
+        let content_type = match attachment.charset {
+            Some(charset) if attachment.content_type.starts_with("text/") => {
+                format!("{}; charset={}", attachment.content_type, charset)
+            }
+            _ => attachment.content_type,
+        };
+        let disposition = format!(
+            "{}; filename*=UTF-8''{}",
+            if download { "attachment" } else { "inline" },
+            encode_filename(&attachment.name)
+        );
+
+        let headers = response.headers_mut();
+        if let Ok(value) = HeaderValue::from_str(&content_type) {
+            headers.insert(header::CONTENT_TYPE, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&disposition) {
+            headers.insert(header::CONTENT_DISPOSITION, value);
+        }
+    }
+
+    response
+}
+
+/// Looks up the attachment addressed by a path relative to the mailboxes
+/// directory (`{mailbox}/{mail}[/messages/{id}]*/attachments/{name}`).
+fn find_attachment(path: &std::path::Path, uri: &str) -> Option<api::Attachment> {
+    let segments = uri
+        .trim_start_matches('/')
+        .split('/')
+        .map(fs::percent_decode)
+        .collect::<Vec<_>>();
+
+    let (name, rest) = segments.split_last()?;
+    let (dir, rest) = rest.split_last()?;
+    let (mailbox, rest) = rest.split_first()?;
+    let (id, nested) = rest.split_first()?;
+
+    if dir != "attachments" || nested.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut mail = id.clone();
+    for pair in nested.chunks(2) {
+        if pair[0] != "messages" {
+            return None;
+        }
+        mail.push('.');
+        mail.push_str(&pair[1]);
+    }
+
+    fs::Mailboxes {
+        path: path.to_path_buf(),
+    }
+    .mailbox(mailbox)
+    .ok()??
+    .mail(&mail)
+    .ok()??
+    .attachment_metadata()
+    .ok()?
+    .into_iter()
+    .find(|attachment| &attachment.name == name)
+}
+
+/// Downloads the original message as `.eml` file.
+#[utoipa::path(
+    get,
+    path = "/download/{mailbox}/{mail}/message.eml",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 200, description = "The original message as attachment", content_type = "message/rfc822", body = String),
+        (status = 404, description = "Mail not found", body = String),
+    ),
+)]
+async fn download_eml(
+    State(MailboxesPath(path)): State<MailboxesPath>,
+    Path((mailbox, mail)): Path<(String, String)>,
+) -> AxumResponse {
+    download(&path, &mailbox, &mail, |mail| {
+        Ok((
+            format!("{}.eml", mail.file_stem()?),
+            "message/rfc822",
+            mail.raw()?,
+        ))
+    })
+}
+
+/// Downloads all attachments of a mail as zip archive.
+#[utoipa::path(
+    get,
+    path = "/download/{mailbox}/{mail}/attachments.zip",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 200, description = "Zip archive of the attachments", content_type = "application/zip", body = String),
+        (status = 404, description = "Mail not found", body = String),
+    ),
+)]
+async fn download_attachments(
+    State(MailboxesPath(path)): State<MailboxesPath>,
+    Path((mailbox, mail)): Path<(String, String)>,
+) -> AxumResponse {
+    download(&path, &mailbox, &mail, |mail| {
+        Ok((
+            format!("{} attachments.zip", mail.file_stem()?),
+            "application/zip",
+            mail.attachments_zip()?,
+        ))
+    })
+}
+
+/// Responds with the file created from the mail as download.
+fn download(
+    path: &std::path::Path,
+    mailbox: &str,
+    mail: &str,
+    file: impl FnOnce(&fs::MailItem) -> Result<(String, &'static str, Vec<u8>), fs::MailError>,
+) -> AxumResponse {
+    let mail = fs::Mailboxes {
+        path: path.to_path_buf(),
+    }
+    .mailbox(mailbox)
+    .and_then(|mailbox| mailbox.map(|mailbox| mailbox.mail(mail)).transpose())
+    .map(Option::flatten);
+
+    let (name, content_type, data) = match mail.and_then(|mail| mail.as_ref().map(file).transpose())
+    {
+        Ok(Some(file)) => file,
+        Ok(None) => return (StatusCode::NOT_FOUND, "mail not found").into_response(),
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename*=UTF-8''{}", encode_filename(&name)),
+            ),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+/// Percent encodes a file name for the extended `filename*` parameter
+/// (RFC 5987).
+pub(crate) fn encode_filename(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Serves the web interface and the APIs on an already bound listener until
+/// `shutdown` completes. `files` is the directory containing the web files,
+/// bundled builds contain them and ignore it.
+pub async fn serve(
+    listener: std::net::TcpListener,
+    mailboxes: &std::path::Path,
+    files: Option<&std::path::Path>,
+    events: Events,
+    index: SharedIndex,
+    shutdown: impl std::future::Future<Output = ()>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     use leptos_axum::{generate_route_list, LeptosRoutes};
 
     let conf = get_configuration(None).await.unwrap();
     let routes = generate_route_list(|| view! { <App/> });
 
-    let app = Router::new().route("/sse", get(sse_handler));
+    let app = Router::new()
+        .route("/sse", get(sse_handler))
+        .route("/ws", get(ws_handler))
+        .route("/wait", get(wait_handler));
 
     let app = {
         #[cfg(feature = "bundle")]
         {
+            let _ = files;
             app.route("/pkg/*rest", get(static_path))
         }
         #[cfg(not(feature = "bundle"))]
         {
-            app.nest_service("/pkg", tower_http::services::fs::ServeDir::new(&args.files))
+            match files {
+                Some(files) => {
+                    app.nest_service("/pkg", tower_http::services::fs::ServeDir::new(files))
+                }
+                None => app,
+            }
         }
     };
 
     let app = app
         .nest_service(
             "/data",
-            tower_http::services::fs::ServeDir::new(&args.mailboxes),
+            tower::ServiceBuilder::new()
+                .layer(axum::middleware::from_fn_with_state(
+                    MailboxesPath(mailboxes.to_path_buf()),
+                    attachment_headers,
+                ))
+                .layer(SetResponseHeaderLayer::overriding(
+                    header::CONTENT_TYPE,
+                    utf8_content_type,
+                ))
+                .service(tower_http::services::fs::ServeDir::new(mailboxes)),
         )
+        .route("/download/:mailbox/:mail/message.eml", get(download_eml))
+        .route(
+            "/download/:mailbox/:mail/attachments.zip",
+            get(download_attachments),
+        )
+        .nest("/api/v1", crate::rest::routes())
         .route(
             "/api/*fn_name",
             get(server_fn_handler).post(server_fn_handler),
         )
         .leptos_routes_with_handler(routes, get(leptos_routes_handler))
         .with_state(Context {
-            path: MailboxesPath(args.mailboxes.clone()),
-            sender,
+            path: MailboxesPath(mailboxes.to_path_buf()),
+            events,
+            index,
             leptos_options: conf.leptos_options,
         });
 
-    let addr: SocketAddr = args.listen_http.parse()?;
-    println!("http server listining on {}", addr);
+    println!("http server listining on {}", listener.local_addr()?);
 
-    axum::Server::bind(&addr)
+    axum::Server::from_tcp(listener)?
         .serve(app.into_make_service())
-        .await
-        .unwrap();
+        .with_graceful_shutdown(shutdown)
+        .await?;
 
     Ok(())
 }
 
+enum StreamItem {
+    Event(Arc<Envelope>),
+    /// Events were lost, optionally with the id of the latest event.
+    Resync(Option<u64>),
+}
+
+/// Id of the last event a reconnecting client received.
+fn last_event_id(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Stream of the missed events followed by all new events.
+fn event_stream(events: &Events, last: Option<u64>) -> impl Stream<Item = StreamItem> {
+    let (replay, receiver) = events.subscribe(last);
+
+    let replay = match replay {
+        Replay::Events(events) => events.into_iter().map(StreamItem::Event).collect(),
+        Replay::Resync(latest) => vec![StreamItem::Resync(Some(latest))],
+    };
+
+    // lagging receivers skip the oldest events
+    let live = BroadcastStream::new(receiver).map(|item| match item {
+        Ok(envelope) => StreamItem::Event(envelope),
+        Err(_) => StreamItem::Resync(None),
+    });
+
+    tokio_stream::iter(replay).chain(live)
+}
+
+/// Streams the store events. Reconnecting clients send the id of the last
+/// received event within `Last-Event-ID` to get the missed events. If
+/// events were lost, a `resync` event tells the client to reload its state.
+///
+/// The events can be restricted by the query parameters `mailbox`,
+/// `domain`, `sender` and `subject`, see [`Subscription`].
+#[utoipa::path(
+    get,
+    path = "/sse",
+    tag = "events",
+    params(
+        Subscription,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last received event"),
+    ),
+    responses(
+        (status = 200, description = "Stream of the events", content_type = "text/event-stream", body = QueueItem),
+        (status = 400, description = "Invalid subject", body = String),
+    ),
+)]
 async fn sse_handler(
     State(context): State<Context>,
-) -> Sse<impl Stream<Item = Result<Event, &'static str>>> {
-    let receiver = BroadcastStream::new(context.sender.subscribe());
+    Query(subscription): Query<Subscription>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>, (StatusCode, String)> {
+    let filter = Filter::new(subscription)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("invalid subject: {}", err)))?;
+
+    let stream =
+        event_stream(&context.events, last_event_id(&headers)).filter(move |item| match item {
+            StreamItem::Event(envelope) => filter.matches(&envelope.item),
+            StreamItem::Resync(_) => true,
+        });
 
-    Sse::new(receiver.map(|mailbox| {
-        Event::default()
-            .json_data(mailbox.map_err(|_| "failed queue")?)
-            .map_err(|_| "failed json")
+    Ok(Sse::new(stream.map(|item| {
+        match item {
+            StreamItem::Event(envelope) => Event::default()
+                .id(envelope.id.to_string())
+                .json_data(&envelope.item),
+            StreamItem::Resync(latest) => {
+                let event = Event::default().event("resync").data("");
+
+                Ok(match latest {
+                    Some(id) => event.id(id.to_string()),
+                    None => event,
+                })
+            }
+        }
     }))
-    .keep_alive(KeepAlive::default())
+    .keep_alive(KeepAlive::default()))
+}
+
+/// Command sent by a WebSocket client. The optional `request` is echoed
+/// within the response to the command.
+#[derive(serde::Deserialize)]
+struct ClientMessage {
+    #[serde(default)]
+    request: Option<serde_json::Value>,
+    #[serde(flatten)]
+    command: Command,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    /// Adds a filter or replaces the filter with the same name.
+    Subscribe {
+        #[serde(default)]
+        name: String,
+        #[serde(flatten)]
+        subscription: Subscription,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        name: String,
+    },
+    MarkRead {
+        mailbox: String,
+        mail: String,
+        #[serde(default = "default_read")]
+        read: bool,
+    },
+    Delete {
+        mailbox: String,
+        mail: String,
+    },
+}
+
+fn default_read() -> bool {
+    true
+}
+
+#[derive(serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Event {
+        id: u64,
+        event: &'a QueueItem,
+    },
+    /// Events were lost, the client has to reload its state.
+    Resync {
+        id: Option<u64>,
+    },
+    /// The command succeeded, `found` is false if the addressed mail does
+    /// not exist.
+    Reply {
+        request: Option<serde_json::Value>,
+        found: bool,
+    },
+    Error {
+        request: Option<serde_json::Value>,
+        message: String,
+    },
+}
+
+/// Streams the store events like `/sse` and accepts commands of the client,
+/// see [`Command`]. The query parameters are the initial filter, named with
+/// the empty string. Events are sent if any filter of the client matches.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "events",
+    params(
+        Subscription,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Id of the last received event"),
+    ),
+    responses(
+        (status = 101, description = "WebSocket sending the events as JSON text messages", body = QueueItem),
+        (status = 400, description = "Invalid subject", body = String),
+    ),
+)]
+async fn ws_handler(
+    State(context): State<Context>,
+    Query(subscription): Query<Subscription>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<AxumResponse, (StatusCode, String)> {
+    let filter = Filter::new(subscription)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("invalid subject: {}", err)))?;
+    let last = last_event_id(&headers);
+
+    Ok(ws.on_upgrade(move |socket| ws_session(socket, context, filter, last)))
+}
+
+async fn ws_session(mut socket: WebSocket, context: Context, filter: Filter, last: Option<u64>) {
+    let store = context.store();
+    let mut filters = HashMap::from([(String::new(), filter)]);
+    let mut events = Box::pin(event_stream(&context.events, last));
+
+    loop {
+        let reply = tokio::select! {
+            item = events.next() => match item {
+                Some(StreamItem::Event(envelope)) => {
+                    if !filters.values().any(|filter| filter.matches(&envelope.item)) {
+                        continue;
+                    }
+
+                    serde_json::to_string(&ServerMessage::Event {
+                        id: envelope.id,
+                        event: &envelope.item,
+                    })
+                }
+                Some(StreamItem::Resync(id)) => serde_json::to_string(&ServerMessage::Resync { id }),
+                None => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    serde_json::to_string(&handle_command(&store, &mut filters, &text))
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // pings are answered by axum
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(err) => {
+                eprintln!("could not serialize websocket message: {}", err);
+                continue;
+            }
+        };
+
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn handle_command(
+    store: &Store,
+    filters: &mut HashMap<String, Filter>,
+    text: &str,
+) -> ServerMessage<'static> {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(err) => {
+            return ServerMessage::Error {
+                request: None,
+                message: format!("invalid command: {}", err),
+            }
+        }
+    };
+
+    let result = match message.command {
+        Command::Subscribe { name, subscription } => Filter::new(subscription)
+            .map(|filter| {
+                filters.insert(name, filter);
+                true
+            })
+            .map_err(|err| format!("invalid subject: {}", err)),
+        Command::Unsubscribe { name } => Ok(filters.remove(&name).is_some()),
+        Command::MarkRead {
+            mailbox,
+            mail,
+            read,
+        } => store
+            .set_read(&mailbox, &mail, read)
+            .map_err(|err| err.to_string()),
+        Command::Delete { mailbox, mail } => store
+            .delete_mail(&mailbox, &mail)
+            .map_err(|err| err.to_string()),
+    };
+
+    match result {
+        Ok(found) => ServerMessage::Reply {
+            request: message.request,
+            found,
+        },
+        Err(err) => ServerMessage::Error {
+            request: message.request,
+            message: err,
+        },
+    }
+}
+
+/// Waits until a mail matching the query is stored and responds with it.
+/// Mails stored before the request count as well, so tests should restrict
+/// the mails by `since`. Responds with `408 Request Timeout` if no mail
+/// arrived in time.
+#[utoipa::path(
+    get,
+    path = "/wait",
+    tag = "events",
+    params(api::WaitQuery),
+    responses(
+        (status = 200, description = "The matching mail", body = api::WaitedMail),
+        (status = 400, description = "Invalid query", body = String),
+        (status = 408, description = "No matching mail arrived in time", body = String),
+    ),
+)]
+async fn wait_handler(
+    State(context): State<Context>,
+    Query(query): Query<api::WaitQuery>,
+) -> Result<Json<api::WaitedMail>, (StatusCode, String)> {
+    let since = query
+        .since
+        .as_deref()
+        .map(|since| {
+            api::parse_timestamp(since).ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    String::from("invalid timestamp for `since`"),
+                )
+            })
+        })
+        .transpose()?;
+    let filter = Filter::new(Subscription {
+        mailbox: query.mailbox.clone(),
+        domain: query.domain.clone(),
+        sender: query.sender.clone(),
+        subject: query.subject.clone(),
+    })
+    .map_err(|err| (StatusCode::BAD_REQUEST, format!("invalid subject: {}", err)))?;
+    let timeout = query
+        .timeout
+        .unwrap_or(api::WAIT_TIMEOUT)
+        .min(api::MAX_WAIT_TIMEOUT);
+
+    // subscribed before scanning the store, so mails stored in between are
+    // not missed
+    let (_, mut receiver) = context.events.subscribe(None);
+
+    let wait = async {
+        if let Some(found) = find_mail(&context, &query, since)? {
+            return Ok(found);
+        }
+
+        loop {
+            match receiver.recv().await {
+                Ok(envelope) => {
+                    if let QueueItem::MailCreated { mailbox, mail, .. } = &envelope.item {
+                        let received = mail.id.parse::<u64>().unwrap_or_default();
+
+                        if filter.matches(&envelope.item)
+                            && since.map(|since| received >= since).unwrap_or(true)
+                        {
+                            return Ok(api::WaitedMail {
+                                mailbox: mailbox.clone(),
+                                mail: mail.clone(),
+                            });
+                        }
+                    }
+                }
+                // the skipped events may contain the mail
+                Err(RecvError::Lagged(_)) => {
+                    if let Some(found) = find_mail(&context, &query, since)? {
+                        return Ok(found);
+                    }
+                }
+                Err(RecvError::Closed) => {
+                    return Err((
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        String::from("server is shutting down"),
+                    ))
+                }
+            }
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(timeout), wait)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                String::from("no matching mail received"),
+            )
+        })?
+        .map(Json)
+}
+
+/// Returns the newest stored mail matching the query.
+fn find_mail(
+    context: &Context,
+    query: &api::WaitQuery,
+    since: Option<u64>,
+) -> Result<Option<api::WaitedMail>, (StatusCode, String)> {
+    let internal = |err: String| (StatusCode::INTERNAL_SERVER_ERROR, err);
+    let list_query = api::MailboxQuery {
+        sender: query.sender.clone(),
+        subject: query.subject.clone(),
+        subject_regex: true,
+        since: since.map(|since| since.to_string()),
+        ..Default::default()
+    };
+
+    let mailboxes = fs::Mailboxes {
+        path: context.path.0.clone(),
+    }
+    .mailboxes()
+    .map_err(|err| internal(err.to_string()))?;
+
+    let mut found: Option<(u64, api::WaitedMail)> = None;
+
+    for mailbox in mailboxes {
+        let id = mailbox.id();
+
+        if query
+            .mailbox
+            .as_ref()
+            .map(|v| !v.eq_ignore_ascii_case(&id))
+            .unwrap_or(false)
+            || query
+                .domain
+                .as_ref()
+                .map(|v| !v.eq_ignore_ascii_case(api::domain_of(&id)))
+                .unwrap_or(false)
+        {
+            continue;
+        }
+
+        let page = api::list_mailbox(&mailbox, &list_query, None, 1)
+            .map_err(|err| internal(err.to_string()))?;
+
+        if let Some(mail) = page.items.into_iter().next() {
+            let received = mail.id.parse::<u64>().unwrap_or_default();
+
+            if found
+                .as_ref()
+                .map(|(newest, _)| received > *newest)
+                .unwrap_or(true)
+            {
+                found = Some((received, api::WaitedMail { mailbox: id, mail }));
+            }
+        }
+    }
+
+    Ok(found.map(|(_, found)| found))
 }
@@ -1,33 +1,87 @@
-//!
+//! Mail catcher for debugging purposes written in Leptos.
 
 pub mod api;
+#[cfg(any(feature = "ssr", feature = "hydrate"))]
 pub mod app;
 #[cfg(feature = "ssr")]
+pub mod calendar;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "ssr")]
+pub mod events;
+#[cfg(feature = "ssr")]
 pub mod http;
 #[cfg(feature = "ssr")]
 pub mod mail;
+#[cfg(feature = "ssr")]
+pub mod openapi;
+#[cfg(feature = "ssr")]
+pub mod preview;
+#[cfg(feature = "ssr")]
+pub mod rest;
+#[cfg(feature = "ssr")]
+pub mod search;
+#[cfg(feature = "ssr")]
+pub mod server;
+#[cfg(feature = "ssr")]
+pub mod store;
 
+/// Change of the store, broadcasted to all connected browsers. Events are
+/// serialized with their kind in the `type` field, e.g.
+/// `{"type":"mail_deleted","mailbox":"a@b.c","mails":["1694000000000"],"unread":1}`.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct QueueItem {
-    obj: MailboxItem,
-    receiver: String,
+#[cfg_attr(feature = "ssr", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueueItem {
+    /// A mail was delivered to a mailbox.
+    MailCreated {
+        mailbox: String,
+        mail: MailboxItem,
+        from: String,
+    },
+    /// Unread mails were marked as read. Ids of embedded messages contain a
+    /// dot.
+    MailRead { mailbox: String, mails: Vec<String> },
+    /// Read mails were marked as unread.
+    MailUnread { mailbox: String, mails: Vec<String> },
+    /// Mails were removed, `unread` of them were not read.
+    MailDeleted {
+        mailbox: String,
+        mails: Vec<String>,
+        unread: i64,
+    },
+    /// A mailbox was created by the delivery of its first mail.
+    MailboxCreated { mailbox: String },
+    /// A mailbox was deleted with all of its mails.
+    MailboxRemoved { mailbox: String },
+    /// All mailboxes were removed.
+    StorePurged,
+}
+
+impl QueueItem {
+    /// Mailbox affected by the event, `None` if all mailboxes are affected.
+    pub fn mailbox(&self) -> Option<&str> {
+        match self {
+            QueueItem::MailCreated { mailbox, .. }
+            | QueueItem::MailRead { mailbox, .. }
+            | QueueItem::MailUnread { mailbox, .. }
+            | QueueItem::MailDeleted { mailbox, .. }
+            | QueueItem::MailboxCreated { mailbox }
+            | QueueItem::MailboxRemoved { mailbox } => Some(mailbox),
+            QueueItem::StorePurged => None,
+        }
+    }
 }
 
 #[cfg(feature = "ssr")]
 fn files_dir() -> std::path::PathBuf {
     let root: Option<std::path::PathBuf> = match std::env::var("LEPTOS_SITE_ROOT") {
-        Ok(val) => Some(
-            val.try_into()
-                .expect("LEPTOS_SITE_ROOT is not a valid path"),
-        ),
+        Ok(val) => Some(val.into()),
         Err(_) => None,
     };
 
     let dir: Option<std::path::PathBuf> = match std::env::var("LEPTOS_SITE_PKG_DIR") {
-        Ok(val) => Some(
-            val.try_into()
-                .expect("LEPTOS_SITE_PKG_DIR is not a valid path"),
-        ),
+        Ok(val) => Some(val.into()),
         Err(_) => None,
     };
 
@@ -3,7 +3,8 @@
 //! Everything mail related is stored in the file system. Each
 //! recipient has its own mailbox. Each mailbox contains mails.
 
-use mail_parser::{Message, MimeHeaders};
+use mail_parser::decoders::charsets::map::charset_decoder;
+use mail_parser::{Message, MimeHeaders, PartType};
 use mailin::{Action, Handler, Response, SessionBuilder};
 use std::path::StripPrefixError;
 use std::{
@@ -17,16 +18,24 @@ use std::{
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::{TcpListener, TcpStream},
-    sync::broadcast::Sender,
 };
 
-use crate::api::MailboxItem;
-use crate::{Args, QueueItem};
+use crate::api::{self, display_addresses, Address, Calendar, Header, MailboxItem, Part};
+use crate::events::Events;
+use crate::search::SharedIndex;
+use crate::QueueItem;
 
 fn try_exists(path: &Path) -> Result<bool, MailError> {
     path.try_exists().map_err(|err| MailError {
         kind: MailErrorKind::FileOpen(err),
-        path: path.to_path_buf().into(),
+        path: path.to_path_buf(),
+    })
+}
+
+fn remove_dir(path: &Path) -> Result<(), MailError> {
+    std::fs::remove_dir_all(path).map_err(|err| MailError {
+        kind: MailErrorKind::DirRemove(err),
+        path: path.to_path_buf(),
     })
 }
 
@@ -67,9 +76,13 @@ impl MailError {
             | MailErrorKind::FileWrite(err)
             | MailErrorKind::FileExists(err)
             | MailErrorKind::FileMetadata(err)
+            | MailErrorKind::FileRemove(err)
             | MailErrorKind::DirCreate(err)
-            | MailErrorKind::DirRead(err) => Some(err),
-            MailErrorKind::SerdeRead(_) | MailErrorKind::SerdeWrite(_) => None,
+            | MailErrorKind::DirRead(err)
+            | MailErrorKind::DirRemove(err) => Some(err),
+            MailErrorKind::SerdeRead(_)
+            | MailErrorKind::SerdeWrite(_)
+            | MailErrorKind::ZipWrite(_) => None,
         }
     }
 }
@@ -81,10 +94,13 @@ pub enum MailErrorKind {
     FileWrite(std::io::Error),
     FileExists(std::io::Error),
     FileMetadata(std::io::Error),
+    FileRemove(std::io::Error),
     SerdeWrite(serde_json::Error),
     SerdeRead(serde_json::Error),
     DirRead(std::io::Error),
     DirCreate(std::io::Error),
+    DirRemove(std::io::Error),
+    ZipWrite(zip::result::ZipError),
 }
 
 impl std::error::Error for MailError {
@@ -95,10 +111,13 @@ impl std::error::Error for MailError {
             MailErrorKind::FileWrite(err) => Some(err),
             MailErrorKind::FileExists(err) => Some(err),
             MailErrorKind::FileMetadata(err) => Some(err),
+            MailErrorKind::FileRemove(err) => Some(err),
             MailErrorKind::SerdeWrite(err) => Some(err),
             MailErrorKind::SerdeRead(err) => Some(err),
             MailErrorKind::DirRead(err) => Some(err),
             MailErrorKind::DirCreate(err) => Some(err),
+            MailErrorKind::DirRemove(err) => Some(err),
+            MailErrorKind::ZipWrite(err) => Some(err),
         }
     }
 }
@@ -117,6 +136,9 @@ impl std::fmt::Display for MailError {
             MailErrorKind::FileMetadata(err) => {
                 write!(f, "FileMetadata[{}]: {}", self.path.display(), err)
             }
+            MailErrorKind::FileRemove(err) => {
+                write!(f, "FileRemove[{}]: {}", self.path.display(), err)
+            }
             MailErrorKind::SerdeWrite(err) => {
                 write!(f, "SerdeWrite[{}]: {}", self.path.display(), err)
             }
@@ -127,6 +149,12 @@ impl std::fmt::Display for MailError {
             MailErrorKind::DirCreate(err) => {
                 write!(f, "DirCreate[{}]: {}", self.path.display(), err)
             }
+            MailErrorKind::DirRemove(err) => {
+                write!(f, "DirRemove[{}]: {}", self.path.display(), err)
+            }
+            MailErrorKind::ZipWrite(err) => {
+                write!(f, "ZipWrite[{}]: {}", self.path.display(), err)
+            }
         }
     }
 }
@@ -142,7 +170,7 @@ impl Mailboxes {
                 .metadata()
                 .map_err(|err| MailError {
                     kind: MailErrorKind::FileMetadata(err),
-                    path: entry.path().into(),
+                    path: entry.path(),
                 })
                 .map(|meta| {
                     if meta.is_dir() {
@@ -155,6 +183,10 @@ impl Mailboxes {
     }
 
     pub fn mailbox(&self, mailbox: &str) -> Result<Option<Mailbox>, MailError> {
+        if !is_valid_mailbox(mailbox) {
+            return Ok(None);
+        }
+
         let path = self.path.join(mailbox);
 
         if try_exists(&path)? {
@@ -172,6 +204,14 @@ impl Mailboxes {
             Ok(None)
         }
     }
+
+    /// Removes all mailboxes and returns their ids.
+    pub fn purge(&self) -> Result<Vec<String>, MailError> {
+        self.mailboxes()?
+            .into_iter()
+            .map(|mailbox| mailbox.delete().map(|_| mailbox.id()))
+            .collect()
+    }
 }
 
 pub struct Mailbox {
@@ -189,7 +229,7 @@ impl Mailbox {
                 .metadata()
                 .map_err(|err| MailError {
                     kind: MailErrorKind::FileMetadata(err),
-                    path: entry.path().into(),
+                    path: entry.path(),
                 })
                 .map(|meta| {
                     if meta.is_dir() {
@@ -201,8 +241,23 @@ impl Mailbox {
         })
     }
 
+    /// Resolves a mail by its id. Ids of messages embedded into other mails
+    /// consist of the id of the parent mail and the part id, separated by a
+    /// dot (e.g. `1694000000000.3`).
     pub fn mail(&self, mail: &str) -> Result<Option<MailItem>, MailError> {
-        let path = self.path.join(mail);
+        if mail
+            .split('.')
+            .any(|v| v.is_empty() || v.contains(['/', '\\']))
+        {
+            return Ok(None);
+        }
+
+        let mut components = mail.split('.');
+        let mut path = self.path.join(components.next().unwrap_or_default());
+
+        for part in components {
+            path = path.join("messages").join(part);
+        }
 
         if try_exists(&path)? {
             let meta = path.metadata().map_err(|err| MailError {
@@ -220,13 +275,41 @@ impl Mailbox {
         }
     }
 
+    pub fn delete(&self) -> Result<(), MailError> {
+        remove_dir(&self.path)
+    }
+
+    /// Removes all mails but keeps the mailbox and returns the ids of the
+    /// removed mails.
+    pub fn empty(&self) -> Result<Vec<String>, MailError> {
+        self.mails()?
+            .into_iter()
+            .map(|mail| mail.delete().map(|_| mail.id()))
+            .collect()
+    }
+
+    /// Marks all mails as read and returns the ids of the mails which were
+    /// unread.
+    pub fn mark_read(&self) -> Result<Vec<String>, MailError> {
+        let mut ids = Vec::new();
+
+        for mail in self.mails()? {
+            if !mail.read()? {
+                mail.set_read()?;
+                ids.push(mail.id());
+            }
+        }
+
+        Ok(ids)
+    }
+
     pub fn unread(&self) -> Result<i64, MailError> {
         read_dir(&self.path, |entry| {
             entry
                 .metadata()
                 .map_err(|err| MailError {
                     kind: MailErrorKind::FileMetadata(err),
-                    path: entry.path().into(),
+                    path: entry.path(),
                 })
                 .and_then(|meta| {
                     if meta.is_dir() {
@@ -250,103 +333,197 @@ pub struct MailItem {
 
 impl MailItem {
     pub fn id(&self) -> String {
-        self.path.file_name().unwrap().to_str().unwrap().to_string()
+        let mut ids = vec![self.path.file_name().unwrap().to_str().unwrap()];
+        let mut parent = self.path.parent();
+
+        // embedded messages are stored within the messages directory of
+        // the parent mail
+        while let Some(path) = parent.filter(|p| p.file_name() == Some("messages".as_ref())) {
+            match path.parent() {
+                Some(mail) if mail.join("metadata.json").exists() => {
+                    ids.push(mail.file_name().unwrap().to_str().unwrap());
+                    parent = mail.parent();
+                }
+                _ => break,
+            }
+        }
+
+        ids.reverse();
+        ids.join(".")
+    }
+
+    /// Id of the mail containing this mail as an embedded message.
+    pub fn parent_id(&self) -> Option<String> {
+        self.id()
+            .rsplit_once('.')
+            .map(|(parent, _)| parent.to_string())
     }
 
-    pub fn new(path: PathBuf, message: &Message, subject: String) -> Result<(), MailError> {
+    pub fn create(
+        path: PathBuf,
+        message: &Message,
+        subject: String,
+        thread: Option<String>,
+    ) -> Result<Self, MailError> {
         let me = Self { path };
 
-        me.init(message, subject)
+        me.init(message, subject, thread)?;
+
+        Ok(me)
     }
 
-    fn init(&self, message: &Message, subject: String) -> Result<(), MailError> {
+    fn init(
+        &self,
+        message: &Message,
+        subject: String,
+        thread: Option<String>,
+    ) -> Result<(), MailError> {
+        let (charsets, warnings) = body_charsets(message);
+
         {
             let mut file = File::create(self.metadata_path()).map_err(|err| MailError {
                 kind: MailErrorKind::FileOpen(err),
-                path: self.metadata_path().into(),
+                path: self.metadata_path(),
             })?;
             serde_json::to_writer(
                 &mut file,
                 &Metadata {
                     subject,
-                    id: self.path.file_name().unwrap().to_string_lossy().to_string(),
-                    from: match message.from() {
-                        mail_parser::HeaderValue::Address(addr) => addr
-                            .address
-                            .as_ref()
-                            .map(|v| v.clone().into_owned())
-                            .unwrap_or(String::new()),
-                        mail_parser::HeaderValue::Text(x) => x.clone().into_owned(),
-                        mail_parser::HeaderValue::Group(_)
-                        | mail_parser::HeaderValue::AddressList(_)
-                        | mail_parser::HeaderValue::GroupList(_)
-                        | mail_parser::HeaderValue::TextList(_)
-                        | mail_parser::HeaderValue::DateTime(_)
-                        | mail_parser::HeaderValue::ContentType(_)
-                        | mail_parser::HeaderValue::Empty => String::new(),
-                    },
+                    id: self.id(),
+                    from: display_addresses(&addresses(message.from())),
                     date: message.date().map(|date| date.to_rfc3339()),
+                    to: addresses(message.to()),
+                    cc: addresses(message.cc()),
+                    reply_to: addresses(message.reply_to()),
+                    message_id: message.message_id().map(|id| id.to_string()),
+                    in_reply_to: text_list(message.in_reply_to()),
+                    references: text_list(message.references()),
+                    list: raw_headers(message.raw_message(), message.headers())
+                        .into_iter()
+                        .filter(|header| header.name.to_lowercase().starts_with("list-"))
+                        .collect(),
+                    charsets,
+                    warnings,
+                    thread,
                 },
             )
             .map_err(|err| MailError {
                 kind: MailErrorKind::SerdeWrite(err),
-                path: self.metadata_path().into(),
+                path: self.metadata_path(),
             })?;
         }
 
         {
-            let mut file = File::create(self.html_path()).map_err(|err| MailError {
+            let mut file = File::create(self.headers_path()).map_err(|err| MailError {
+                kind: MailErrorKind::FileOpen(err),
+                path: self.headers_path(),
+            })?;
+            serde_json::to_writer(
+                &mut file,
+                &raw_headers(message.raw_message(), message.headers()),
+            )
+            .map_err(|err| MailError {
+                kind: MailErrorKind::SerdeWrite(err),
+                path: self.headers_path(),
+            })?;
+        }
+
+        let structure = {
+            let parts_dir = self.parts_path();
+
+            std::fs::create_dir(&parts_dir).map_err(|err| MailError {
+                kind: MailErrorKind::DirCreate(err),
+                path: parts_dir.clone(),
+            })?;
+
+            let structure = self.store_part(message, 0)?;
+
+            let mut file = File::create(self.structure_path()).map_err(|err| MailError {
                 kind: MailErrorKind::FileOpen(err),
-                path: self.html_path().into(),
+                path: self.structure_path(),
+            })?;
+            serde_json::to_writer(&mut file, &structure).map_err(|err| MailError {
+                kind: MailErrorKind::SerdeWrite(err),
+                path: self.structure_path(),
             })?;
 
-            for idx in &message.html_body {
-                file.write_all(
-                    message
-                        .part(*idx)
-                        .unwrap()
-                        .text_contents()
-                        .unwrap()
-                        .as_ref(),
-                )
-                .map_err(|err| MailError {
-                    kind: MailErrorKind::FileWrite(err),
-                    path: self.html_path().into(),
+            structure
+        };
+
+        {
+            let calendars = message
+                .parts
+                .iter()
+                .enumerate()
+                .filter(|(_, part)| {
+                    matches!(
+                        mime_type(part).as_str(),
+                        "text/calendar" | "application/ics"
+                    )
+                })
+                .map(|(id, part)| {
+                    let text = String::from_utf8_lossy(part.contents());
+                    crate::calendar::parse(id, &text)
+                })
+                .collect::<Vec<_>>();
+
+            if !calendars.is_empty() {
+                let mut file = File::create(self.calendars_path()).map_err(|err| MailError {
+                    kind: MailErrorKind::FileOpen(err),
+                    path: self.calendars_path(),
+                })?;
+                serde_json::to_writer(&mut file, &calendars).map_err(|err| MailError {
+                    kind: MailErrorKind::SerdeWrite(err),
+                    path: self.calendars_path(),
                 })?;
             }
         }
 
+        // bodies are decoded from their declared charset by the parser and
+        // always stored as UTF-8
+        {
+            let mut file = File::create(self.html_path()).map_err(|err| MailError {
+                kind: MailErrorKind::FileOpen(err),
+                path: self.html_path(),
+            })?;
+
+            for pos in 0..message.html_body_count() {
+                if let Some(html) = message.body_html(pos) {
+                    let html = resolve_cids(&html, &structure);
+
+                    file.write_all(html.as_bytes()).map_err(|err| MailError {
+                        kind: MailErrorKind::FileWrite(err),
+                        path: self.html_path(),
+                    })?;
+                }
+            }
+        }
+
         {
             let mut file = File::create(self.text_path()).map_err(|err| MailError {
                 kind: MailErrorKind::FileOpen(err),
-                path: self.text_path().into(),
+                path: self.text_path(),
             })?;
 
-            for idx in &message.text_body {
-                file.write_all(
-                    message
-                        .part(*idx)
-                        .unwrap()
-                        .text_contents()
-                        .unwrap()
-                        .as_ref(),
-                )
-                .map_err(|err| MailError {
-                    kind: MailErrorKind::FileWrite(err),
-                    path: self.text_path().into(),
-                })?;
+            for pos in 0..message.text_body_count() {
+                if let Some(text) = message.body_text(pos) {
+                    file.write_all(text.as_bytes()).map_err(|err| MailError {
+                        kind: MailErrorKind::FileWrite(err),
+                        path: self.text_path(),
+                    })?;
+                }
             }
         }
 
         {
             let mut file = std::fs::File::create(self.raw_path()).map_err(|err| MailError {
                 kind: MailErrorKind::FileOpen(err),
-                path: self.raw_path().into(),
+                path: self.raw_path(),
             })?;
             file.write_all(&message.raw_message)
                 .map_err(|err| MailError {
                     kind: MailErrorKind::FileWrite(err),
-                    path: self.raw_path().into(),
+                    path: self.raw_path(),
                 })?;
         }
 
@@ -358,7 +535,10 @@ impl MailItem {
                 path: attachment_dir.clone(),
             })?;
 
-            for part in message.attachments() {
+            let mut attachments = Vec::new();
+
+            for id in &message.attachments {
+                let part = &message.parts[*id];
                 let is_file = part
                     .content_disposition()
                     .map(|v| v.is_attachment())
@@ -367,28 +547,140 @@ impl MailItem {
 
                 if is_file {
                     if let Some(name) = file_name {
-                        let path = attachment_dir.join(name);
+                        let name = unique_file_name(&attachment_dir, name);
+                        let path = attachment_dir.join(&name);
+                        let content = attached_content(message, part);
 
                         let mut file = std::fs::File::create(&path).map_err(|err| MailError {
                             kind: MailErrorKind::FileOpen(err),
                             path: path.clone(),
                         })?;
-                        file.write_all(part.contents()).map_err(|err| MailError {
+                        file.write_all(&content).map_err(|err| MailError {
                             kind: MailErrorKind::FileWrite(err),
                             path: path.clone(),
                         })?;
+
+                        attachments.push(api::Attachment {
+                            name,
+                            content_type: mime_type(part),
+                            charset: part
+                                .content_type()
+                                .and_then(|ct| ct.attribute("charset"))
+                                .map(|v| v.to_string()),
+                            size: content.len(),
+                            declared_size: part
+                                .content_disposition()
+                                .and_then(|cd| cd.attribute("size"))
+                                .and_then(|size| size.trim().parse().ok()),
+                            encoding: transfer_encoding(part),
+                            sha256: sha256(&content),
+                            part: Some(*id),
+                        });
                     }
                 }
             }
+
+            let mut file =
+                File::create(self.attachment_metadata_path()).map_err(|err| MailError {
+                    kind: MailErrorKind::FileOpen(err),
+                    path: self.attachment_metadata_path(),
+                })?;
+            serde_json::to_writer(&mut file, &attachments).map_err(|err| MailError {
+                kind: MailErrorKind::SerdeWrite(err),
+                path: self.attachment_metadata_path(),
+            })?;
         }
 
         Ok(())
     }
 
+    /// Stores the decoded content of the part and all of its sub parts
+    /// into the parts directory.
+    fn store_part(&self, message: &Message, id: usize) -> Result<Part, MailError> {
+        let part = &message.parts[id];
+
+        let children = part
+            .sub_parts()
+            .unwrap_or_default()
+            .iter()
+            .map(|id| self.store_part(message, *id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let content_type = mime_type(part);
+
+        let message_id = match part.message() {
+            Some(nested) => {
+                let item = MailItem {
+                    path: self.messages_path().join(id.to_string()),
+                };
+
+                std::fs::create_dir_all(&item.path).map_err(|err| MailError {
+                    kind: MailErrorKind::DirCreate(err),
+                    path: item.path.clone(),
+                })?;
+
+                let subject = nested
+                    .subject()
+                    .map(|v| v.to_string())
+                    .unwrap_or_else(|| item.id());
+                item.init(nested, subject, None)?;
+
+                Some(item.id())
+            }
+            None => None,
+        };
+
+        let (file, decoded_size) = if part.is_multipart() {
+            (None, None)
+        } else {
+            let name = match extension(&content_type, part.attachment_name()) {
+                Some(ext) => format!("{}.{}", id, ext),
+                None => format!("{}", id),
+            };
+            let path = self.parts_path().join(&name);
+
+            let mut file = File::create(&path).map_err(|err| MailError {
+                kind: MailErrorKind::FileOpen(err),
+                path: path.clone(),
+            })?;
+            file.write_all(part.contents()).map_err(|err| MailError {
+                kind: MailErrorKind::FileWrite(err),
+                path: path.clone(),
+            })?;
+
+            (Some(format!("parts/{}", name)), Some(part.contents().len()))
+        };
+
+        Ok(Part {
+            id,
+            charset: part
+                .content_type()
+                .and_then(|ct| ct.attribute("charset"))
+                .map(|v| v.to_string()),
+            encoding: transfer_encoding(part),
+            disposition: part
+                .content_disposition()
+                .map(|cd| cd.c_type.to_lowercase()),
+            name: part.attachment_name().map(|v| v.to_string()),
+            content_id: part.content_id().map(|v| v.to_string()),
+            size: part.offset_end.saturating_sub(part.offset_body),
+            decoded_size,
+            file,
+            message: message_id,
+            headers: raw_headers(message.raw_message(), &part.headers),
+            content_type,
+            children,
+        })
+    }
+
     pub fn metadata_path(&self) -> PathBuf {
         self.path.join("metadata.json")
     }
 
+    pub fn headers_path(&self) -> PathBuf {
+        self.path.join("headers.json")
+    }
+
     pub fn raw_path(&self) -> PathBuf {
         self.path.join("body.raw")
     }
@@ -405,6 +697,26 @@ impl MailItem {
         self.path.join("attachments")
     }
 
+    pub fn messages_path(&self) -> PathBuf {
+        self.path.join("messages")
+    }
+
+    pub fn calendars_path(&self) -> PathBuf {
+        self.path.join("calendars.json")
+    }
+
+    pub fn attachment_metadata_path(&self) -> PathBuf {
+        self.path.join("attachments.json")
+    }
+
+    pub fn parts_path(&self) -> PathBuf {
+        self.path.join("parts")
+    }
+
+    pub fn structure_path(&self) -> PathBuf {
+        self.path.join("structure.json")
+    }
+
     pub fn raw(&self) -> Result<Vec<u8>, MailError> {
         let path = self.raw_path();
         let mut file = File::open(&path).map_err(|err| MailError {
@@ -421,11 +733,52 @@ impl MailItem {
         Ok(string)
     }
 
+    /// Decodes the raw message for display. Messages containing 8bit
+    /// content which is not UTF-8 are decoded with the first supported
+    /// charset of `charsets`.
+    pub fn raw_text(&self, charsets: &[String]) -> Result<Decoded, MailError> {
+        let raw = self.raw()?;
+
+        let decoded = match String::from_utf8(raw) {
+            Ok(content) => Decoded {
+                content,
+                warning: None,
+            },
+            Err(err) => {
+                let bytes = err.into_bytes();
+
+                // the content is known to be no UTF-8
+                match charsets
+                    .iter()
+                    .filter(|charset| !is_utf8_charset(charset))
+                    .find_map(|charset| charset_decoder(charset.as_bytes()).map(|d| (charset, d)))
+                {
+                    Some((charset, decoder)) => Decoded {
+                        content: decoder(&bytes),
+                        warning: Some(format!(
+                            "raw message is not valid UTF-8, decoded as `{}`",
+                            charset
+                        )),
+                    },
+                    None => Decoded {
+                        content: String::from_utf8_lossy(&bytes).into_owned(),
+                        warning: Some(
+                            "raw message is not valid UTF-8, invalid characters were replaced"
+                                .to_string(),
+                        ),
+                    },
+                }
+            }
+        };
+
+        Ok(decoded)
+    }
+
     pub fn has_html(&self) -> Result<bool, MailError> {
         try_exists(&self.html_path())
     }
 
-    pub fn html(&self) -> Result<Option<String>, MailError> {
+    pub fn html(&self) -> Result<Option<Decoded>, MailError> {
         if self.has_html()? {
             let path = self.html_path();
             let mut file = File::open(&path).map_err(|err| MailError {
@@ -439,7 +792,7 @@ impl MailItem {
                 path: path.clone(),
             })?;
 
-            Ok(Some(String::from_utf8(string).unwrap()))
+            Ok(Some(Decoded::utf8(string, "HTML body")))
         } else {
             Ok(None)
         }
@@ -449,7 +802,7 @@ impl MailItem {
         try_exists(&self.text_path())
     }
 
-    pub fn text(&self) -> Result<Option<String>, MailError> {
+    pub fn text(&self) -> Result<Option<Decoded>, MailError> {
         if self.has_text()? {
             let path = self.text_path();
             let mut file = File::open(&path).map_err(|err| MailError {
@@ -462,7 +815,8 @@ impl MailItem {
                 kind: MailErrorKind::FileRead(err),
                 path: path.clone(),
             })?;
-            Ok(Some(String::from_utf8(string).unwrap()))
+
+            Ok(Some(Decoded::utf8(string, "text body")))
         } else {
             Ok(None)
         }
@@ -483,6 +837,83 @@ impl MailItem {
         Ok(json)
     }
 
+    pub fn headers(&self) -> Result<Vec<Header>, MailError> {
+        let path = self.headers_path();
+
+        // mails stored before headers were captured have no header file
+        if !try_exists(&path)? {
+            return Ok(Vec::new());
+        }
+
+        let file = File::open(&path).map_err(|err| MailError {
+            kind: MailErrorKind::FileOpen(err),
+            path: path.clone(),
+        })?;
+        let reader = std::io::BufReader::new(file);
+        let json = serde_json::from_reader(reader).map_err(|err| MailError {
+            kind: MailErrorKind::SerdeRead(err),
+            path: path.clone(),
+        })?;
+
+        Ok(json)
+    }
+
+    pub fn structure(&self) -> Result<Option<Part>, MailError> {
+        let path = self.structure_path();
+
+        // mails stored before the structure was captured have no structure
+        if !try_exists(&path)? {
+            return Ok(None);
+        }
+
+        let file = File::open(&path).map_err(|err| MailError {
+            kind: MailErrorKind::FileOpen(err),
+            path: path.clone(),
+        })?;
+        let reader = std::io::BufReader::new(file);
+        let json = serde_json::from_reader(reader).map_err(|err| MailError {
+            kind: MailErrorKind::SerdeRead(err),
+            path: path.clone(),
+        })?;
+
+        Ok(Some(json))
+    }
+
+    /// Reads the decoded content of a part, `None` for parts without
+    /// content (e.g. multipart parts).
+    pub fn part_data(&self, part: &Part) -> Result<Option<Vec<u8>>, MailError> {
+        let path = match &part.file {
+            Some(file) => self.path.join(file),
+            None => return Ok(None),
+        };
+
+        std::fs::read(&path).map(Some).map_err(|err| MailError {
+            kind: MailErrorKind::FileRead(err),
+            path,
+        })
+    }
+
+    pub fn calendars(&self) -> Result<Vec<Calendar>, MailError> {
+        let path = self.calendars_path();
+
+        // only mails containing calendars have a calendar file
+        if !try_exists(&path)? {
+            return Ok(Vec::new());
+        }
+
+        let file = File::open(&path).map_err(|err| MailError {
+            kind: MailErrorKind::FileOpen(err),
+            path: path.clone(),
+        })?;
+        let reader = std::io::BufReader::new(file);
+        let json = serde_json::from_reader(reader).map_err(|err| MailError {
+            kind: MailErrorKind::SerdeRead(err),
+            path: path.clone(),
+        })?;
+
+        Ok(json)
+    }
+
     fn read_path(&self) -> PathBuf {
         self.path.join("read")
     }
@@ -515,6 +946,193 @@ impl MailItem {
         Ok(())
     }
 
+    pub fn set_unread(&self) -> Result<(), MailError> {
+        let path = self.read_path();
+
+        if try_exists(&path)? {
+            std::fs::remove_file(&path).map_err(|err| MailError {
+                kind: MailErrorKind::FileRemove(err),
+                path: path.clone(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the mail including its embedded messages.
+    pub fn delete(&self) -> Result<(), MailError> {
+        remove_dir(&self.path)
+    }
+
+    pub fn attachment_metadata(&self) -> Result<Vec<api::Attachment>, MailError> {
+        let path = self.attachment_metadata_path();
+
+        // mails stored before the metadata was captured derive it from the
+        // stored files
+        if !try_exists(&path)? {
+            return self
+                .attachments()?
+                .into_iter()
+                .map(|attachment| {
+                    let data = attachment.data()?;
+                    let name = attachment.id();
+
+                    Ok(api::Attachment {
+                        content_type: mime_guess::from_path(&name)
+                            .first_or_octet_stream()
+                            .essence_str()
+                            .to_string(),
+                        charset: None,
+                        size: data.len(),
+                        declared_size: None,
+                        encoding: None,
+                        sha256: sha256(&data),
+                        part: None,
+                        name,
+                    })
+                })
+                .collect();
+        }
+
+        let file = File::open(&path).map_err(|err| MailError {
+            kind: MailErrorKind::FileOpen(err),
+            path: path.clone(),
+        })?;
+        let reader = std::io::BufReader::new(file);
+        let json = serde_json::from_reader(reader).map_err(|err| MailError {
+            kind: MailErrorKind::SerdeRead(err),
+            path: path.clone(),
+        })?;
+
+        Ok(json)
+    }
+
+    /// Reads a textual attachment and decodes it from its declared charset.
+    pub fn attachment_text(&self, attachment: &api::Attachment) -> Result<Decoded, MailError> {
+        let data = Attachment {
+            path: self.attachments_path().join(&attachment.name),
+        }
+        .data()?;
+
+        let decoded = match String::from_utf8(data) {
+            Ok(content) => Decoded {
+                content,
+                warning: None,
+            },
+            Err(err) => {
+                let bytes = err.into_bytes();
+
+                match attachment
+                    .charset
+                    .as_ref()
+                    .filter(|charset| !is_utf8_charset(charset))
+                    .and_then(|charset| charset_decoder(charset.as_bytes()))
+                {
+                    Some(decoder) => Decoded {
+                        content: decoder(&bytes),
+                        warning: None,
+                    },
+                    None => Decoded {
+                        content: String::from_utf8_lossy(&bytes).into_owned(),
+                        warning: Some(format!(
+                            "attachment `{}` is not valid UTF-8, invalid characters were replaced",
+                            attachment.name
+                        )),
+                    },
+                }
+            }
+        };
+
+        Ok(decoded)
+    }
+
+    /// File name (without extension) for downloads of the mail, derived
+    /// from the subject.
+    pub fn file_stem(&self) -> Result<String, MailError> {
+        let subject = self.metadata()?.subject;
+        let stem = subject
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.' | '(' | ')') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .take(100)
+            .collect::<String>();
+        let stem = stem.trim().trim_matches('.');
+
+        if stem.is_empty() {
+            Ok(self.id())
+        } else {
+            Ok(stem.to_string())
+        }
+    }
+
+    /// Packs all attachments into a zip archive.
+    pub fn attachments_zip(&self) -> Result<Vec<u8>, MailError> {
+        let path = self.attachments_path();
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+        for attachment in self.attachments()? {
+            zip.start_file(attachment.id(), options)
+                .map_err(|err| MailError {
+                    kind: MailErrorKind::ZipWrite(err),
+                    path: attachment.path.clone(),
+                })?;
+            zip.write_all(&attachment.data()?)
+                .map_err(|err| MailError {
+                    kind: MailErrorKind::FileWrite(err),
+                    path: attachment.path.clone(),
+                })?;
+        }
+
+        let cursor = zip.finish().map_err(|err| MailError {
+            kind: MailErrorKind::ZipWrite(err),
+            path,
+        })?;
+
+        Ok(cursor.into_inner())
+    }
+
+    /// Messages embedded into this mail (e.g. forwarded messages).
+    pub fn messages(&self) -> Result<Vec<MailItem>, MailError> {
+        let path = self.messages_path();
+
+        if !try_exists(&path)? {
+            return Ok(Vec::new());
+        }
+
+        let mut vec = read_dir(&path, |e| {
+            let val = if e
+                .file_type()
+                .map_err(|err| MailError {
+                    kind: MailErrorKind::FileMetadata(err),
+                    path: e.path(),
+                })?
+                .is_dir()
+            {
+                Some(MailItem { path: e.path() })
+            } else {
+                None
+            };
+
+            Ok(val)
+        })?;
+
+        vec.sort_by_key(|mail| {
+            mail.path
+                .file_name()
+                .and_then(|v| v.to_str())
+                .and_then(|v| v.parse::<usize>().ok())
+        });
+
+        Ok(vec)
+    }
+
     pub fn attachments(&self) -> Result<Vec<Attachment>, MailError> {
         let path = self.path.join("attachments");
 
@@ -523,7 +1141,7 @@ impl MailItem {
                 .file_type()
                 .map_err(|err| MailError {
                     kind: MailErrorKind::FileMetadata(err),
-                    path: e.path().into(),
+                    path: e.path(),
                 })?
                 .is_file()
             {
@@ -561,22 +1179,557 @@ impl Attachment {
         Ok(string)
     }
 
-    pub fn relative<'a>(&'a self, base: &'a Path) -> Result<&Path, StripPrefixError> {
+    pub fn relative<'a>(&'a self, base: &'a Path) -> Result<&'a Path, StripPrefixError> {
         base.strip_prefix(&self.path)
     }
 }
 
+/// Text read from the store together with a description of any problem
+/// encountered while decoding it.
+#[derive(Debug)]
+pub struct Decoded {
+    pub content: String,
+    pub warning: Option<String>,
+}
+
+impl Decoded {
+    fn utf8(bytes: Vec<u8>, name: &str) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(content) => Self {
+                content,
+                warning: None,
+            },
+            Err(err) => Self {
+                content: String::from_utf8_lossy(err.as_bytes()).into_owned(),
+                warning: Some(format!(
+                    "stored {} is not valid UTF-8, invalid characters were replaced",
+                    name
+                )),
+            },
+        }
+    }
+
+    /// Returns the content and moves the warning into `warnings`.
+    pub fn into_content(self, warnings: &mut Vec<String>) -> String {
+        warnings.extend(self.warning);
+        self.content
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, PartialEq, Eq, Clone)]
 pub struct Metadata {
     pub id: String,
     pub subject: String,
     pub from: String,
     pub date: Option<String>,
+    #[serde(default)]
+    pub to: Vec<Address>,
+    #[serde(default)]
+    pub cc: Vec<Address>,
+    #[serde(default)]
+    pub reply_to: Vec<Address>,
+    #[serde(default)]
+    pub message_id: Option<String>,
+    #[serde(default)]
+    pub in_reply_to: Vec<String>,
+    #[serde(default)]
+    pub references: Vec<String>,
+    #[serde(default)]
+    pub list: Vec<Header>,
+    #[serde(default)]
+    pub charsets: Vec<String>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Id of the first mail of the conversation, only set for mails
+    /// delivered to a mailbox.
+    #[serde(default)]
+    pub thread: Option<String>,
+}
+
+impl Metadata {
+    /// Thread of the mail, mails without a thread start their own.
+    pub fn thread(&self) -> &str {
+        self.thread.as_deref().unwrap_or(&self.id)
+    }
+}
+
+/// Strips reply and forward prefixes as well as list tags (`[list]`) from
+/// a subject and normalizes its case and whitespace.
+pub(crate) fn normalize_subject(subject: &str) -> String {
+    const PREFIXES: [&str; 8] = ["re", "fw", "fwd", "aw", "wg", "sv", "vs", "antw"];
+
+    let mut rest = subject.trim();
+
+    loop {
+        if let Some(tag) = rest.strip_prefix('[') {
+            match tag.find(']') {
+                Some(end) => {
+                    rest = tag[end + 1..].trim_start();
+                    continue;
+                }
+                None => break,
+            }
+        }
+
+        let prefix = rest
+            .find(':')
+            .map(|pos| (&rest[..pos], &rest[pos + 1..]))
+            .and_then(|(prefix, tail)| {
+                // counters like `Re[2]:` or `Re(2):`
+                let word = prefix.trim_end_matches(|c: char| {
+                    c.is_ascii_digit() || matches!(c, '[' | ']' | '(' | ')' | ' ')
+                });
+
+                PREFIXES
+                    .iter()
+                    .any(|v| v.eq_ignore_ascii_case(word))
+                    .then_some(tail)
+            });
+
+        match prefix {
+            Some(tail) => rest = tail.trim_start(),
+            None => break,
+        }
+    }
+
+    rest.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Whether the charset is UTF-8 or a subset of it. mail-parser decodes these
+/// without a charset decoder, so they are supported but have none.
+fn is_utf8_charset(charset: &str) -> bool {
+    const CHARSETS: [&str; 7] = [
+        "utf-8",
+        "utf8",
+        "us-ascii",
+        "ascii",
+        "us",
+        "ansi_x3.4-1968",
+        "iso646-us",
+    ];
+
+    CHARSETS
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(charset.trim()))
+}
+
+/// Collects the declared charsets of all body parts and describes
+/// problems encountered while decoding them.
+fn body_charsets(message: &Message) -> (Vec<String>, Vec<String>) {
+    let mut charsets: Vec<String> = Vec::new();
+    let mut warnings = Vec::new();
+    let mut seen = Vec::new();
+
+    for idx in message.html_body.iter().chain(message.text_body.iter()) {
+        if seen.contains(idx) {
+            continue;
+        }
+        seen.push(*idx);
+
+        let part = match message.part(*idx) {
+            Some(part) => part,
+            None => continue,
+        };
+        let text = match &part.body {
+            PartType::Text(text) | PartType::Html(text) => text,
+            _ => continue,
+        };
+        let charset = part.content_type().and_then(|ct| ct.attribute("charset"));
+
+        if let Some(charset) = charset {
+            if !charsets.iter().any(|c| c.eq_ignore_ascii_case(charset)) {
+                charsets.push(charset.to_string());
+            }
+
+            if !is_utf8_charset(charset) && charset_decoder(charset.as_bytes()).is_none() {
+                warnings.push(format!(
+                    "part {}: unsupported charset `{}`, decoded as UTF-8 with replacements",
+                    idx, charset
+                ));
+                continue;
+            }
+        }
+
+        if part.is_encoding_problem {
+            warnings.push(format!(
+                "part {}: malformed content, stored as found in the message",
+                idx
+            ));
+        } else if text.contains(char::REPLACEMENT_CHARACTER) {
+            warnings.push(format!(
+                "part {}: invalid characters for charset `{}` were replaced",
+                idx,
+                charset.unwrap_or("us-ascii")
+            ));
+        }
+    }
+
+    (charsets, warnings)
+}
+
+fn address(addr: &mail_parser::Addr, group: Option<&str>) -> Address {
+    Address {
+        name: addr.name.as_ref().map(|v| v.to_string()),
+        address: addr.address.as_ref().map(|v| v.to_string()),
+        group: group.map(|v| v.to_string()),
+    }
+}
+
+fn group_addresses(group: &mail_parser::Group) -> Vec<Address> {
+    let name = group.name.as_deref().unwrap_or_default();
+
+    if group.addresses.is_empty() {
+        vec![Address {
+            name: None,
+            address: None,
+            group: Some(name.to_string()),
+        }]
+    } else {
+        group
+            .addresses
+            .iter()
+            .map(|addr| address(addr, Some(name)))
+            .collect()
+    }
+}
+
+/// Flattens every address form of a header value (single address, list,
+/// groups) into a list of addresses.
+fn addresses(value: &mail_parser::HeaderValue) -> Vec<Address> {
+    match value {
+        mail_parser::HeaderValue::Address(addr) => vec![address(addr, None)],
+        mail_parser::HeaderValue::AddressList(list) => {
+            list.iter().map(|addr| address(addr, None)).collect()
+        }
+        mail_parser::HeaderValue::Group(group) => group_addresses(group),
+        mail_parser::HeaderValue::GroupList(groups) => {
+            groups.iter().flat_map(group_addresses).collect()
+        }
+        mail_parser::HeaderValue::Text(text) => {
+            let text = text.trim();
+            let is_address = text.contains('@') && !text.contains(char::is_whitespace);
+
+            vec![Address {
+                name: (!is_address).then(|| text.to_string()),
+                address: is_address.then(|| text.to_string()),
+                group: None,
+            }]
+        }
+        mail_parser::HeaderValue::TextList(_)
+        | mail_parser::HeaderValue::DateTime(_)
+        | mail_parser::HeaderValue::ContentType(_)
+        | mail_parser::HeaderValue::Empty => Vec::new(),
+    }
+}
+
+/// Collects the unique recipient addresses of the To, Cc and Bcc headers.
+fn recipients(message: &Message) -> Vec<String> {
+    let mut vec: Vec<String> = Vec::new();
+
+    for value in [message.to(), message.cc(), message.bcc()] {
+        for addr in addresses(value) {
+            if let Some(address) = addr.address {
+                if !vec.contains(&address) {
+                    vec.push(address);
+                }
+            }
+        }
+    }
+
+    vec
+}
+
+/// Mailboxes are directories named after the recipient, hence the
+/// recipient must be usable as a single path component.
+fn is_valid_mailbox(receiver: &str) -> bool {
+    !receiver.is_empty()
+        && receiver != "."
+        && receiver != ".."
+        && !receiver.contains(['/', '\\', '\0'])
+}
+
+pub(crate) fn text_list(value: &mail_parser::HeaderValue) -> Vec<String> {
+    match value {
+        mail_parser::HeaderValue::Text(text) => vec![text.to_string()],
+        mail_parser::HeaderValue::TextList(list) => list.iter().map(|v| v.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the MIME type of a part, falling back to the defaults of RFC
+/// 2045 and RFC 2046 if the part has no content type.
+fn mime_type(part: &mail_parser::MessagePart) -> String {
+    match part.content_type() {
+        Some(ct) => match &ct.c_subtype {
+            Some(subtype) => format!("{}/{}", ct.c_type, subtype).to_lowercase(),
+            None => ct.c_type.to_lowercase(),
+        },
+        None => match part.body {
+            PartType::Text(_) => "text/plain".to_string(),
+            PartType::Html(_) => "text/html".to_string(),
+            PartType::Message(_) => "message/rfc822".to_string(),
+            PartType::Multipart(_) => "multipart/mixed".to_string(),
+            PartType::Binary(_) | PartType::InlineBinary(_) => {
+                "application/octet-stream".to_string()
+            }
+        },
+    }
+}
+
+fn transfer_encoding(part: &mail_parser::MessagePart) -> Option<String> {
+    part.content_transfer_encoding()
+        .map(|v| v.to_lowercase())
+        .or_else(|| match part.encoding {
+            mail_parser::Encoding::QuotedPrintable => Some("quoted-printable".to_string()),
+            mail_parser::Encoding::Base64 => Some("base64".to_string()),
+            mail_parser::Encoding::None => None,
+        })
+}
+
+/// Content of a part as attached by the sender. mail-parser converts the
+/// content of text parts to UTF-8, so these are decoded from the raw message
+/// again to keep their declared charset.
+fn attached_content<'a>(
+    message: &'a Message,
+    part: &'a mail_parser::MessagePart,
+) -> std::borrow::Cow<'a, [u8]> {
+    use mail_parser::decoders::base64::base64_decode;
+
+    let is_text = matches!(part.body, PartType::Text(_) | PartType::Html(_));
+    let raw = message.raw_message.get(part.offset_body..part.offset_end);
+
+    let decoded = match raw.filter(|_| is_text && !part.is_encoding_problem) {
+        Some(raw) => match part.encoding {
+            mail_parser::Encoding::None => Some(raw.to_vec()),
+            mail_parser::Encoding::QuotedPrintable => decode_quoted_printable(raw),
+            mail_parser::Encoding::Base64 => base64_decode(raw),
+        },
+        None => None,
+    };
+
+    match decoded {
+        Some(decoded) => std::borrow::Cow::Owned(decoded),
+        None => std::borrow::Cow::Borrowed(part.contents()),
+    }
+}
+
+/// Decodes quoted-printable text, hard line breaks stand for CRLF (RFC
+/// 2045) while mail-parser decodes them as LF.
+fn decode_quoted_printable(raw: &[u8]) -> Option<Vec<u8>> {
+    use mail_parser::decoders::quoted_printable::quoted_printable_decode;
+
+    let mut decoded = Vec::with_capacity(raw.len());
+    let mut lines = raw.split(|b| *b == b'\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        match line.strip_suffix(b"=") {
+            Some(soft) => decoded.extend(quoted_printable_decode(soft)?),
+            None => {
+                decoded.extend(quoted_printable_decode(line)?);
+
+                if lines.peek().is_some() {
+                    decoded.extend_from_slice(b"\r\n");
+                }
+            }
+        }
+    }
+
+    Some(decoded)
+}
+
+/// Hex encoded SHA-256 hash of the data.
+fn sha256(data: &[u8]) -> String {
+    use sha2::Digest;
+
+    sha2::Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Turns an attachment name into a file name which is unique within the
+/// directory. Path separators are replaced, since the name is chosen by
+/// the sender.
+fn unique_file_name(dir: &Path, name: &str) -> String {
+    let name = name
+        .replace(['/', '\\', '\0'], "_")
+        .trim_start_matches('.')
+        .to_string();
+    let name = if name.is_empty() {
+        String::from("attachment")
+    } else {
+        name
+    };
+
+    if !dir.join(&name).exists() {
+        return name;
+    }
+
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (name.as_str(), None),
+    };
+
+    (2..)
+        .map(|idx| match ext {
+            Some(ext) => format!("{} ({}).{}", stem, idx, ext),
+            None => format!("{} ({})", stem, idx),
+        })
+        .find(|name| !dir.join(name).exists())
+        .unwrap()
+}
+
+/// Picks a file extension which maps back to the MIME type, so that the
+/// file is served with the correct content type. The extension of the
+/// attachment name is preferred, followed by common extensions.
+fn extension(mime: &str, name: Option<&str>) -> Option<String> {
+    const COMMON: [&str; 5] = ["txt", "html", "eml", "vcf", "jpg"];
+
+    let maps_back = |ext: &&str| {
+        mime_guess::from_ext(ext)
+            .first()
+            .map(|guess| guess.essence_str() == mime)
+            .unwrap_or(false)
+    };
+
+    let subtype = mime.split_once('/').map(|(_, subtype)| subtype);
+
+    name.and_then(|name| Path::new(name).extension())
+        .and_then(|ext| ext.to_str())
+        .into_iter()
+        .chain(COMMON)
+        .chain(subtype)
+        .chain(
+            mime_guess::get_mime_extensions_str(mime)
+                .unwrap_or_default()
+                .iter()
+                .copied(),
+        )
+        .find(maps_back)
+        .map(|ext| ext.to_lowercase())
+}
+
+fn collect_cids<'a>(part: &'a Part, cids: &mut Vec<(&'a str, &'a str)>) {
+    if let (Some(cid), Some(file)) = (&part.content_id, &part.file) {
+        cids.push((cid, file));
+    }
+
+    for child in &part.children {
+        collect_cids(child, cids);
+    }
+}
+
+pub(crate) fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        let decoded = if bytes[idx] == b'%' {
+            value
+                .get(idx + 1..idx + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+
+        match decoded {
+            Some(byte) => {
+                out.push(byte);
+                idx += 3;
+            }
+            None => {
+                out.push(bytes[idx]);
+                idx += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Rewrites `cid:` URLs (RFC 2392) referencing parts of the message to the
+/// stored part. The paths are relative to the mail directory, which is
+/// also the location of the HTML body.
+fn resolve_cids(html: &str, structure: &Part) -> String {
+    let mut cids = Vec::new();
+    collect_cids(structure, &mut cids);
+
+    if cids.is_empty() {
+        return html.to_string();
+    }
+
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let mut last = 0;
+    let mut search = 0;
+
+    while let Some(pos) = lower[search..].find("cid:").map(|pos| pos + search) {
+        let start = pos + "cid:".len();
+        search = start;
+
+        // ignore words ending with cid, e.g. `acid:`
+        if html[..pos]
+            .chars()
+            .next_back()
+            .map(|c| c.is_alphanumeric())
+            .unwrap_or(false)
+        {
+            continue;
+        }
+
+        let end = html[start..]
+            .find(|c: char| matches!(c, '"' | '\'' | ')' | '<' | '>') || c.is_whitespace())
+            .map(|end| end + start)
+            .unwrap_or(html.len());
+        let id = percent_decode(&html[start..end]);
+
+        if let Some((_, file)) = cids
+            .iter()
+            .find(|(cid, _)| *cid == id)
+            .or_else(|| cids.iter().find(|(cid, _)| cid.eq_ignore_ascii_case(&id)))
+        {
+            out.push_str(&html[last..pos]);
+            out.push_str(file);
+            last = end;
+        }
+
+        search = end;
+    }
+
+    out.push_str(&html[last..]);
+    out
+}
+
+/// Collects headers in their original order and spelling.
+fn raw_headers(raw: &[u8], headers: &[mail_parser::Header]) -> Vec<Header> {
+    headers
+        .iter()
+        .map(|header| {
+            let name = raw
+                .get(header.offset_field()..header.offset_start())
+                .map(String::from_utf8_lossy)
+                .map(|name| name.trim_end().trim_end_matches(':').trim_end().to_string())
+                .unwrap_or_else(|| header.name().to_string());
+            let value = raw
+                .get(header.offset_start()..header.offset_end())
+                .map(|value| String::from_utf8_lossy(value).trim().to_string())
+                .unwrap_or_default();
+
+            Header { name, value }
+        })
+        .collect()
 }
 
 #[derive(Clone)]
 struct MyHandler {
-    channel: Sender<Arc<QueueItem>>,
+    events: Events,
+    index: SharedIndex,
     path: Arc<PathBuf>,
     addresses: Vec<String>,
     buffer: Vec<u8>,
@@ -620,19 +1773,18 @@ impl Handler for MyHandler {
             };
 
             let receivers = if self.addresses.is_empty() {
-                match message.to() {
-                    mail_parser::HeaderValue::Address(ref addr) => {
-                        vec![addr.address.as_ref().unwrap().to_string()]
-                    }
-                    _ => {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            "missing TO header in mail",
-                        ))
-                    }
+                let receivers = recipients(&message);
+
+                if receivers.is_empty() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "missing recipient (TO, CC or BCC header) in mail",
+                    ));
                 }
+
+                receivers
             } else {
-                std::mem::replace(&mut self.addresses, Vec::new())
+                std::mem::take(&mut self.addresses)
             };
 
             println!("received email for: {:?}", receivers);
@@ -640,11 +1792,19 @@ impl Handler for MyHandler {
             let since_the_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
             let id = format!("{}", since_the_epoch.as_millis());
             let subject = message.subject().unwrap_or_else(|| &id).to_string();
+            let from = display_addresses(&addresses(message.from()));
 
             for receiver in receivers {
+                if !is_valid_mailbox(&receiver) {
+                    println!("skipping invalid receiver: {:?}", receiver);
+                    continue;
+                }
+
                 let postbox = self.path.join(&receiver);
 
                 let try_block = || {
+                    let created = !postbox.exists();
+
                     if !ensure_dir(&postbox)? {
                         return Err(std::io::Error::new(
                             std::io::ErrorKind::AlreadyExists,
@@ -652,36 +1812,77 @@ impl Handler for MyHandler {
                         ));
                     }
 
+                    // a poisoned index fails the delivery before anything is
+                    // stored, the client retries it later. The index is only
+                    // locked to look up the thread and to add the stored mail,
+                    // searches don't wait on the disk.
+                    let thread = self
+                        .index
+                        .read()
+                        .map_err(|_| std::io::Error::other("search index is poisoned"))?
+                        .thread_of(&receiver, &message)
+                        .unwrap_or_else(|| id.clone());
+
                     let mail_path = postbox.join(&id);
 
                     std::fs::create_dir(&mail_path)?;
 
-                    match MailItem::new(mail_path, &message, subject.clone()) {
-                        Ok(_) => {
+                    match MailItem::create(
+                        mail_path.clone(),
+                        &message,
+                        subject.clone(),
+                        Some(thread.clone()),
+                    ) {
+                        Ok(mail) => {
                             println!("stored email for: {}", receiver);
+
+                            match self.index.write() {
+                                Ok(mut index) => {
+                                    if let Err(err) = index.insert(&receiver, &mail) {
+                                        println!(
+                                            "failed to index email for `{}`: {}",
+                                            receiver, err
+                                        );
+                                    }
+                                }
+                                Err(_) => {
+                                    println!(
+                                        "failed to index email for `{}`: index is poisoned",
+                                        receiver
+                                    )
+                                }
+                            }
                         }
                         Err(err) => {
                             println!("failed stored email for `{}`: {}", receiver, err);
-                            match err.io_error() {
-                                Some(io) => return Err(io),
-                                None => {
-                                    todo!()
-                                }
-                            }
+                            // the client retries the delivery, a partially
+                            // stored mail would show up twice
+                            let _ = std::fs::remove_dir_all(&mail_path);
+
+                            let message = err.to_string();
+                            return Err(err
+                                .io_error()
+                                .unwrap_or_else(|| std::io::Error::other(message)));
                         }
                     }
 
-                    let _ = self.channel.send(
-                        QueueItem {
-                            obj: MailboxItem {
-                                subject: subject.to_string(),
-                                id: id.clone(),
-                                read: false,
-                            },
-                            receiver,
-                        }
-                        .into(),
-                    );
+                    if created {
+                        self.events.publish(QueueItem::MailboxCreated {
+                            mailbox: receiver.clone(),
+                        });
+                    }
+
+                    self.events.publish(QueueItem::MailCreated {
+                        mail: MailboxItem {
+                            subject: subject.to_string(),
+                            id: id.clone(),
+                            read: false,
+                            thread,
+                            thread_size: None,
+                        },
+                        mailbox: receiver,
+                        from: from.clone(),
+                    });
 
                     Ok(())
                 };
@@ -709,17 +1910,20 @@ impl Handler for MyHandler {
     }
 }
 
-pub async fn listen(
-    args: &Args,
-    channel: Sender<Arc<QueueItem>>,
+/// Accepts mails on an already bound listener and stores them below
+/// `mailboxes`.
+pub async fn serve(
+    listener: TcpListener,
+    mailboxes: PathBuf,
+    events: Events,
+    index: SharedIndex,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    println!("mail server listining on {}", args.listen_mail);
-
-    let listener = TcpListener::bind(&args.listen_mail).await?;
+    println!("mail server listining on {}", listener.local_addr()?);
 
     let handler = MyHandler {
-        channel,
-        path: Arc::new(args.mailboxes.clone()),
+        events,
+        index,
+        path: Arc::new(mailboxes),
         addresses: Vec::new(),
         buffer: Vec::new(),
     };
@@ -742,7 +1946,7 @@ async fn process(mut stream: TcpStream, handler: MyHandler) -> std::io::Result<(
     let mut read = BufReader::new(read);
     let mut buffer = Vec::new();
 
-    write.write(&session.greeting().buffer()?).await?;
+    write.write_all(&session.greeting().buffer()?).await?;
 
     loop {
         buffer.clear();
@@ -753,10 +1957,10 @@ async fn process(mut stream: TcpStream, handler: MyHandler) -> std::io::Result<(
 
                 match res.action {
                     Action::Reply => {
-                        write.write(&res.buffer()?).await?;
+                        write.write_all(&res.buffer()?).await?;
                     }
                     Action::Close => {
-                        write.write(&res.buffer()?).await?;
+                        write.write_all(&res.buffer()?).await?;
                         write.shutdown().await?;
                         break;
                     }
@@ -770,3 +1974,121 @@ async fn process(mut stream: TcpStream, handler: MyHandler) -> std::io::Result<(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_subjects() {
+        for (subject, expected) in [
+            ("Plans", "plans"),
+            ("  Plans  for\tMonday ", "plans for monday"),
+            ("Re: Plans", "plans"),
+            ("RE: Fwd: aw:WG: Plans", "plans"),
+            ("Re[2]: Plans", "plans"),
+            ("Re (3): Plans", "plans"),
+            ("[list] Re: [list] Plans", "plans"),
+            ("Antw: Sv: VS: Plans", "plans"),
+            ("Über: Plans", "über: plans"),
+            ("Reply: Plans", "reply: plans"),
+            ("Re: Plans: Monday", "plans: monday"),
+            ("[unclosed Plans", "[unclosed plans"),
+            ("Re:", ""),
+            ("", ""),
+        ] {
+            assert_eq!(normalize_subject(subject), expected, "{}", subject);
+        }
+    }
+
+    /// Mail with the same CSV attached as UTF-8 and as ISO-8859-1 in each
+    /// transfer encoding.
+    fn latin1_mail() -> Vec<u8> {
+        let mut raw = b"From: sender@example.com\r\n\
+            To: receiver@example.com\r\n\
+            Subject: Export\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            \r\n\
+            see attachments\r\n"
+            .to_vec();
+
+        for (name, charset, encoding, content) in [
+            (
+                "utf8.csv",
+                "utf-8",
+                "8bit",
+                &b"name\r\nM\xc3\xbcller\r\n"[..],
+            ),
+            ("8bit.csv", "iso-8859-1", "8bit", b"name\r\nM\xfcller\r\n"),
+            (
+                "qp.csv",
+                "iso-8859-1",
+                "quoted-printable",
+                b"na=\r\nme\r\nM=FCller\r\n",
+            ),
+            (
+                "base64.csv",
+                "iso-8859-1",
+                "base64",
+                b"bmFtZQ0KTfxsbGVy\r\n",
+            ),
+        ] {
+            raw.extend_from_slice(
+                format!(
+                    "--b\r\n\
+                    Content-Type: text/csv; charset={}\r\n\
+                    Content-Transfer-Encoding: {}\r\n\
+                    Content-Disposition: attachment; filename=\"{}\"\r\n\
+                    \r\n",
+                    charset, encoding, name
+                )
+                .as_bytes(),
+            );
+            raw.extend_from_slice(content);
+        }
+
+        raw.extend_from_slice(b"--b--\r\n");
+        raw
+    }
+
+    #[test]
+    fn stores_attachments_as_attached() {
+        let path =
+            std::env::temp_dir().join(format!("mail-blackhole-mail-latin1-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        let raw = latin1_mail();
+        let message = Message::parse(&raw).unwrap();
+        let mail = MailItem::create(path.clone(), &message, String::from("Export"), None).unwrap();
+
+        let attachments = mail.attachment_metadata().unwrap();
+        assert_eq!(attachments.len(), 4);
+
+        for attachment in &attachments {
+            let expected: &[u8] = if attachment.name == "utf8.csv" {
+                b"name\r\nM\xc3\xbcller"
+            } else {
+                b"name\r\nM\xfcller"
+            };
+            let data = Attachment {
+                path: mail.attachments_path().join(&attachment.name),
+            }
+            .data()
+            .unwrap();
+
+            assert_eq!(data, expected, "{}", attachment.name);
+            assert_eq!(attachment.size, expected.len(), "{}", attachment.name);
+            assert_eq!(attachment.sha256, sha256(expected), "{}", attachment.name);
+
+            let text = mail.attachment_text(attachment).unwrap();
+            assert_eq!(text.content, "name\r\nMüller", "{}", attachment.name);
+            assert_eq!(text.warning, None);
+        }
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+//! OpenAPI document of the HTTP API and a page to explore it, served at
+//! `/api/v1/openapi.json` and `/api/v1/docs`.
+
+use axum::response::Html;
+use axum::Json;
+use utoipa::OpenApi;
+
+use crate::api::{
+    Address, Attachment, Attendee, Calendar, CalendarEvent, CalendarTime, ErrorBody, Header, Mail,
+    Mailbox, MailboxItem, MailboxPage, MailboxSort, Metadata, Part, WaitedMail,
+};
+use crate::QueueItem;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::rest::mailboxes,
+        crate::rest::purge,
+        crate::rest::mailbox,
+        crate::rest::delete_mailbox,
+        crate::rest::mark_mailbox_read,
+        crate::rest::mails,
+        crate::rest::empty_mailbox,
+        crate::rest::mail,
+        crate::rest::delete_mail,
+        crate::rest::mark_read,
+        crate::rest::mark_unread,
+        crate::rest::raw,
+        crate::rest::parts,
+        crate::rest::part,
+        crate::rest::attachments,
+        crate::rest::attachment,
+        crate::http::wait_handler,
+        crate::http::sse_handler,
+        crate::http::ws_handler,
+        crate::http::download_eml,
+        crate::http::download_attachments,
+    ),
+    components(schemas(
+        Address,
+        Attachment,
+        Attendee,
+        Calendar,
+        CalendarEvent,
+        CalendarTime,
+        ErrorBody,
+        Header,
+        Mail,
+        Mailbox,
+        MailboxItem,
+        MailboxPage,
+        MailboxSort,
+        Metadata,
+        Part,
+        QueueItem,
+        WaitedMail,
+    )),
+    tags(
+        (name = "mailboxes", description = "Mailboxes of the recipients"),
+        (name = "mails", description = "Mails of a mailbox"),
+        (name = "events", description = "Changes of the store and waiting for mails"),
+    )
+)]
+struct ApiDoc;
+
+pub(crate) async fn document() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Page listing the operations of the document, which can be executed
+/// from within the page.
+pub(crate) async fn explorer() -> Html<&'static str> {
+    Html(include_str!("explorer.html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_document() {
+        let document = ApiDoc::openapi().to_json().unwrap();
+        let document: serde_json::Value = serde_json::from_str(&document).unwrap();
+
+        for path in [
+            "/api/v1/mailboxes/{mailbox}/mails/{mail}/parts/{part}",
+            "/sse",
+            "/ws",
+            "/wait",
+            "/download/{mailbox}/{mail}/message.eml",
+            "/download/{mailbox}/{mail}/attachments.zip",
+        ] {
+            assert!(document["paths"][path].is_object(), "{}", path);
+        }
+    }
+}
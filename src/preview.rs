@@ -0,0 +1,265 @@
+//! Prepares textual attachments for a preview within the browser.
+
+use crate::api::{Contact, Header, Preview, PreviewContent, PreviewKind};
+use crate::calendar::{parse_line, unescape, unfold};
+
+pub fn preview(kind: PreviewKind, text: String, mut warnings: Vec<String>) -> Preview {
+    let content = match kind {
+        PreviewKind::Csv => PreviewContent::Table(csv(&text, &mut warnings)),
+        PreviewKind::Json => match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(value) => PreviewContent::Json(serde_json::to_string_pretty(&value).unwrap_or(text)),
+            Err(err) => {
+                warnings.push(format!("invalid JSON: {}", err));
+                PreviewContent::Text(text)
+            }
+        },
+        PreviewKind::VCard => PreviewContent::Contacts(vcard(&text, &mut warnings)),
+        PreviewKind::Text | PreviewKind::Image | PreviewKind::Pdf => PreviewContent::Text(text),
+    };
+
+    Preview { content, warnings }
+}
+
+/// Splits CSV (RFC 4180) into rows. Files using semicolons as separator,
+/// as exported by spreadsheets in many locales, are detected by their
+/// first line.
+fn csv(text: &str, warnings: &mut Vec<String>) -> Vec<Vec<String>> {
+    let first = text.lines().next().unwrap_or_default();
+    let separator = if first.matches(';').count() > first.matches(',').count() {
+        ';'
+    } else {
+        ','
+    };
+
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.is_empty() => in_quotes = true,
+            c if in_quotes => field.push(c),
+            c if c == separator => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        warnings.push(String::from("CSV contains an unterminated quoted field"));
+    }
+
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows
+}
+
+/// Extracts the contacts of a vCard file. Every property besides the
+/// structural ones is listed with its type parameters.
+fn vcard(text: &str, warnings: &mut Vec<String>) -> Vec<Contact> {
+    let mut contacts = Vec::new();
+    let mut contact: Option<Contact> = None;
+
+    for raw in unfold(text) {
+        let line = match parse_line(&raw) {
+            Some(line) => line,
+            None => {
+                warnings.push(format!("not a valid content line: `{}`", raw));
+                continue;
+            }
+        };
+        // properties may be grouped (`item1.EMAIL`)
+        let name = line
+            .name
+            .rsplit_once('.')
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| line.name.clone());
+
+        match (name.as_str(), contact.as_mut()) {
+            ("BEGIN", _) if line.value.eq_ignore_ascii_case("VCARD") => {
+                contact = Some(Contact {
+                    name: None,
+                    fields: Vec::new(),
+                });
+            }
+            ("END", Some(_)) if line.value.eq_ignore_ascii_case("VCARD") => {
+                contacts.extend(contact.take());
+            }
+            ("VERSION" | "PRODID", Some(_)) => (),
+            ("FN", Some(contact)) => contact.name = Some(unescape(&line.value)),
+            (_, Some(contact)) => {
+                let types = line
+                    .params
+                    .iter()
+                    .filter(|(key, _)| key == "TYPE")
+                    .map(|(_, value)| value.to_lowercase())
+                    .collect::<Vec<_>>();
+                let name = if types.is_empty() {
+                    name
+                } else {
+                    format!("{} ({})", name, types.join(", "))
+                };
+                let value = if line.param("ENCODING").is_some() {
+                    String::from("(embedded data)")
+                } else {
+                    // structured values (N, ADR) separate their components
+                    // with semicolons
+                    line.value
+                        .split(';')
+                        .map(unescape)
+                        .filter(|v| !v.is_empty())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+
+                contact.fields.push(Header { name, value });
+            }
+            (_, None) => warnings.push(format!("{} outside of VCARD", line.name)),
+        }
+    }
+
+    if contact.is_some() {
+        warnings.push(String::from("BEGIN:VCARD is never closed"));
+        contacts.extend(contact);
+    }
+
+    contacts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|field| field.to_string()).collect()
+    }
+
+    fn header(name: &str, value: &str) -> Header {
+        Header {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn splits_quoted_csv_fields() {
+        let mut warnings = Vec::new();
+        let rows = csv(
+            "name,comment\r\n\"Doe, John\",\"says \"\"hi\"\"\r\nand bye\"\r\nJane,\r\n",
+            &mut warnings,
+        );
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            rows,
+            vec![
+                row(&["name", "comment"]),
+                row(&["Doe, John", "says \"hi\"\r\nand bye"]),
+                row(&["Jane", ""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_semicolon_separated_csv() {
+        let mut warnings = Vec::new();
+        let rows = csv("name;price\n\"Tea; green\";1,50", &mut warnings);
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            rows,
+            vec![row(&["name", "price"]), row(&["Tea; green", "1,50"])]
+        );
+    }
+
+    #[test]
+    fn warns_about_unterminated_csv_quotes() {
+        let mut warnings = Vec::new();
+        let rows = csv("a,\"b\nc", &mut warnings);
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(rows, vec![row(&["a", "b\nc"])]);
+    }
+
+    #[test]
+    fn unfolds_vcard_lines() {
+        let mut warnings = Vec::new();
+        let contacts = vcard(
+            "BEGIN:VCARD\r\n\
+            VERSION:4.0\r\n\
+            FN:Jane\r\n  Doe\r\n\
+            item1.EMAIL;TYPE=WORK:jane@\r\n\texample.com\r\n\
+            N:Doe;Jane;;;\r\n\
+            NOTE:first\\, second\r\n\
+            END:VCARD\r\n",
+            &mut warnings,
+        );
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(
+            contacts,
+            vec![Contact {
+                name: Some(String::from("Jane Doe")),
+                fields: vec![
+                    header("EMAIL (work)", "jane@example.com"),
+                    header("N", "Doe, Jane"),
+                    header("NOTE", "first, second"),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn warns_about_unclosed_vcards() {
+        let mut warnings = Vec::new();
+        let contacts = vcard("TEL:123\nBEGIN:VCARD\nFN:Jane\n", &mut warnings);
+
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].name.as_deref(), Some("Jane"));
+    }
+
+    #[test]
+    fn pretty_prints_json() {
+        let preview = preview(PreviewKind::Json, String::from(r#"{"a":[1]}"#), Vec::new());
+
+        assert!(preview.warnings.is_empty());
+        assert_eq!(
+            preview.content,
+            PreviewContent::Json(String::from("{\n  \"a\": [\n    1\n  ]\n}"))
+        );
+    }
+
+    #[test]
+    fn shows_invalid_json_as_text() {
+        let text = String::from(r#"{"a": 1"#);
+        let preview = preview(
+            PreviewKind::Json,
+            text.clone(),
+            vec![String::from("earlier")],
+        );
+
+        assert_eq!(preview.warnings.len(), 2);
+        assert_eq!(preview.warnings[0], "earlier");
+        assert!(
+            preview.warnings[1].starts_with("invalid JSON: "),
+            "{:?}",
+            preview.warnings
+        );
+        assert_eq!(preview.content, PreviewContent::Text(text));
+    }
+}
@@ -0,0 +1,573 @@
+//! Versioned REST API for automation, mounted at `/api/v1`.
+//!
+//! Unlike the server functions used by the frontend, the routes and their
+//! encoding are stable. Errors are answered with a JSON body of the form
+//! `{"status": 404, "error": "mail not found"}`. Reading a mail does not
+//! mark it as read.
+
+use axum::extract::rejection::{PathRejection, QueryRejection};
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{any, get, put};
+use axum::{Json, Router};
+
+use crate::api::{self, Attachment, ErrorBody, Mail, Mailbox, MailboxPage, MailboxQuery, Part};
+use crate::http::{encode_filename, Context};
+use crate::mail as fs;
+use crate::store::{Store, StoreError};
+
+pub(crate) fn routes() -> Router<Context> {
+    Router::new()
+        .route("/mailboxes", get(mailboxes).delete(purge))
+        .route("/mailboxes/:mailbox", get(mailbox).delete(delete_mailbox))
+        .route("/mailboxes/:mailbox/read", put(mark_mailbox_read))
+        .route(
+            "/mailboxes/:mailbox/mails",
+            get(mails).delete(empty_mailbox),
+        )
+        .route(
+            "/mailboxes/:mailbox/mails/:mail",
+            get(mail).delete(delete_mail),
+        )
+        .route(
+            "/mailboxes/:mailbox/mails/:mail/read",
+            put(mark_read).delete(mark_unread),
+        )
+        .route("/mailboxes/:mailbox/mails/:mail/raw", get(raw))
+        .route("/mailboxes/:mailbox/mails/:mail/parts", get(parts))
+        .route("/mailboxes/:mailbox/mails/:mail/parts/:part", get(part))
+        .route(
+            "/mailboxes/:mailbox/mails/:mail/attachments",
+            get(attachments),
+        )
+        .route(
+            "/mailboxes/:mailbox/mails/:mail/attachments/:name",
+            get(attachment),
+        )
+        .route("/openapi.json", get(crate::openapi::document))
+        .route("/docs", get(crate::openapi::explorer))
+        // takes precedence over the server functions sharing the prefix
+        .route(
+            "/*path",
+            any(|| async { ApiError::not_found("no such route") }),
+        )
+}
+
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn not_found(message: &str) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            status: self.status.as_u16(),
+            error: self.message,
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> Self {
+        let status = match err {
+            StoreError::Invalid(_) => StatusCode::BAD_REQUEST,
+            StoreError::Mail(_) | StoreError::IndexPoisoned => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        Self {
+            status,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<fs::MailError> for ApiError {
+    fn from(err: fs::MailError) -> Self {
+        StoreError::from(err).into()
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(err: QueryRejection) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: err.body_text(),
+        }
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(err: PathRejection) -> Self {
+        Self {
+            status: err.status(),
+            message: err.body_text(),
+        }
+    }
+}
+
+/// Path parameters of the API, invalid ones are answered with the JSON
+/// error body like all other errors.
+#[derive(FromRequestParts)]
+#[from_request(via(Path), rejection(ApiError))]
+struct ApiPath<T>(T);
+
+type ApiResult<T> = Result<T, ApiError>;
+
+/// Maps the result of a change to `204 No Content` or `404 Not Found` if
+/// the addressed mailbox or mail does not exist.
+fn changed(found: bool, missing: &str) -> ApiResult<StatusCode> {
+    if found {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found(missing))
+    }
+}
+
+fn find_mailbox(store: &Store, mailbox: &str) -> ApiResult<fs::Mailbox> {
+    store
+        .mailboxes
+        .mailbox(mailbox)?
+        .ok_or_else(|| ApiError::not_found("mailbox not found"))
+}
+
+fn find_mail(store: &Store, mailbox: &str, mail: &str) -> ApiResult<fs::MailItem> {
+    find_mailbox(store, mailbox)?
+        .mail(mail)?
+        .ok_or_else(|| ApiError::not_found("mail not found"))
+}
+
+fn file(content_type: &str, name: Option<&str>, data: Vec<u8>) -> Response {
+    let mut response = ([(header::CONTENT_TYPE, content_type.to_string())], data).into_response();
+
+    if let Some(value) = name
+        .map(|name| format!("inline; filename*=UTF-8''{}", encode_filename(name)))
+        .and_then(|value| header::HeaderValue::from_str(&value).ok())
+    {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_DISPOSITION, value);
+    }
+
+    response
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes",
+    tag = "mailboxes",
+    responses(
+        (status = 200, description = "All mailboxes sorted by their id", body = [Mailbox]),
+    ),
+)]
+async fn mailboxes(State(context): State<Context>) -> ApiResult<Json<Vec<Mailbox>>> {
+    Ok(Json(api::list_mailboxes(&context.store().mailboxes)?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/mailboxes",
+    tag = "mailboxes",
+    responses(
+        (status = 204, description = "All mailboxes were deleted"),
+    ),
+)]
+async fn purge(State(context): State<Context>) -> ApiResult<StatusCode> {
+    context.store().purge()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes/{mailbox}",
+    tag = "mailboxes",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+    ),
+    responses(
+        (status = 200, body = Mailbox),
+        (status = 404, description = "Mailbox not found", body = ErrorBody),
+    ),
+)]
+async fn mailbox(
+    State(context): State<Context>,
+    ApiPath(mailbox): ApiPath<String>,
+) -> ApiResult<Json<Mailbox>> {
+    let item = find_mailbox(&context.store(), &mailbox)?;
+
+    Ok(Json(Mailbox {
+        id: item.id(),
+        unread: item.unread()?,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/mailboxes/{mailbox}",
+    tag = "mailboxes",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+    ),
+    responses(
+        (status = 204, description = "The mailbox was deleted"),
+        (status = 404, description = "Mailbox not found", body = ErrorBody),
+    ),
+)]
+async fn delete_mailbox(
+    State(context): State<Context>,
+    ApiPath(mailbox): ApiPath<String>,
+) -> ApiResult<StatusCode> {
+    changed(
+        context.store().delete_mailbox(&mailbox)?,
+        "mailbox not found",
+    )
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/mailboxes/{mailbox}/read",
+    tag = "mailboxes",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+    ),
+    responses(
+        (status = 204, description = "All mails were marked as read"),
+        (status = 404, description = "Mailbox not found", body = ErrorBody),
+    ),
+)]
+async fn mark_mailbox_read(
+    State(context): State<Context>,
+    ApiPath(mailbox): ApiPath<String>,
+) -> ApiResult<StatusCode> {
+    changed(
+        context.store().mark_mailbox_read(&mailbox)?,
+        "mailbox not found",
+    )
+}
+
+/// Page of a mailbox listing, see [`api::list_mailbox`].
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct PageQuery {
+    /// Cursor of the next page, see [`MailboxPage::next`].
+    cursor: Option<String>,
+    /// Number of mails of the page.
+    limit: Option<usize>,
+}
+
+/// Lists the mails of a mailbox. The filters of [`MailboxQuery`] and the
+/// page are given as query parameters.
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes/{mailbox}/mails",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        MailboxQuery,
+        PageQuery,
+    ),
+    responses(
+        (status = 200, body = MailboxPage),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Mailbox not found", body = ErrorBody),
+    ),
+)]
+async fn mails(
+    State(context): State<Context>,
+    ApiPath(mailbox): ApiPath<String>,
+    query: Result<Query<MailboxQuery>, QueryRejection>,
+    page: Result<Query<PageQuery>, QueryRejection>,
+) -> ApiResult<Json<MailboxPage>> {
+    let Query(query) = query?;
+    let Query(page) = page?;
+    let mailbox = find_mailbox(&context.store(), &mailbox)?;
+
+    Ok(Json(api::list_mailbox(
+        &mailbox,
+        &query,
+        page.cursor.as_deref(),
+        page.limit.unwrap_or(api::PAGE_SIZE),
+    )?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/mailboxes/{mailbox}/mails",
+    tag = "mailboxes",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+    ),
+    responses(
+        (status = 204, description = "All mails of the mailbox were deleted"),
+        (status = 404, description = "Mailbox not found", body = ErrorBody),
+    ),
+)]
+async fn empty_mailbox(
+    State(context): State<Context>,
+    ApiPath(mailbox): ApiPath<String>,
+) -> ApiResult<StatusCode> {
+    changed(
+        context.store().empty_mailbox(&mailbox)?,
+        "mailbox not found",
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 200, body = Mail),
+        (status = 404, description = "Mail not found", body = ErrorBody),
+    ),
+)]
+async fn mail(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail)): ApiPath<(String, String)>,
+) -> ApiResult<Json<Mail>> {
+    let mail = find_mail(&context.store(), &mailbox, &mail)?;
+
+    Ok(Json(api::mail_content(&mail)?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 204, description = "The mail was deleted"),
+        (status = 400, description = "Embedded messages can not be deleted", body = ErrorBody),
+        (status = 404, description = "Mail not found", body = ErrorBody),
+    ),
+)]
+async fn delete_mail(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail)): ApiPath<(String, String)>,
+) -> ApiResult<StatusCode> {
+    changed(
+        context.store().delete_mail(&mailbox, &mail)?,
+        "mail not found",
+    )
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}/read",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 204, description = "The mail was marked as read"),
+        (status = 404, description = "Mail not found", body = ErrorBody),
+    ),
+)]
+async fn mark_read(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail)): ApiPath<(String, String)>,
+) -> ApiResult<StatusCode> {
+    changed(
+        context.store().set_read(&mailbox, &mail, true)?,
+        "mail not found",
+    )
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}/read",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 204, description = "The mail was marked as unread"),
+        (status = 404, description = "Mail not found", body = ErrorBody),
+    ),
+)]
+async fn mark_unread(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail)): ApiPath<(String, String)>,
+) -> ApiResult<StatusCode> {
+    changed(
+        context.store().set_read(&mailbox, &mail, false)?,
+        "mail not found",
+    )
+}
+
+/// Original message as received.
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}/raw",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 200, content_type = "message/rfc822", body = String),
+        (status = 404, description = "Mail not found", body = ErrorBody),
+    ),
+)]
+async fn raw(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail)): ApiPath<(String, String)>,
+) -> ApiResult<Response> {
+    let mail = find_mail(&context.store(), &mailbox, &mail)?;
+
+    Ok(file("message/rfc822", None, mail.raw()?))
+}
+
+/// MIME structure of a mail.
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}/parts",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 200, body = Part),
+        (status = 404, description = "Mail or structure not found", body = ErrorBody),
+    ),
+)]
+async fn parts(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail)): ApiPath<(String, String)>,
+) -> ApiResult<Json<Part>> {
+    find_mail(&context.store(), &mailbox, &mail)?
+        .structure()?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("mail has no recorded structure"))
+}
+
+/// Decoded content of a part.
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}/parts/{part}",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+        ("part" = usize, Path, description = "Id of the part"),
+    ),
+    responses(
+        (status = 200, description = "Content with the content type of the part", content_type = "application/octet-stream", body = String),
+        (status = 400, description = "Invalid part id", body = ErrorBody),
+        (status = 404, description = "Mail, part or content not found", body = ErrorBody),
+    ),
+)]
+async fn part(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail, part)): ApiPath<(String, String, usize)>,
+) -> ApiResult<Response> {
+    let mail = find_mail(&context.store(), &mailbox, &mail)?;
+
+    let structure = mail
+        .structure()?
+        .ok_or_else(|| ApiError::not_found("mail has no recorded structure"))?;
+
+    let mut pending = vec![&structure];
+    let found = loop {
+        match pending.pop() {
+            Some(node) if node.id == part => break node,
+            Some(node) => pending.extend(&node.children),
+            None => return Err(ApiError::not_found("part not found")),
+        }
+    };
+
+    let data = mail
+        .part_data(found)?
+        .ok_or_else(|| ApiError::not_found("part has no content"))?;
+
+    Ok(file(&found.content_type, found.name.as_deref(), data))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}/attachments",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+    ),
+    responses(
+        (status = 200, body = [Attachment]),
+        (status = 404, description = "Mail not found", body = ErrorBody),
+    ),
+)]
+async fn attachments(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail)): ApiPath<(String, String)>,
+) -> ApiResult<Json<Vec<Attachment>>> {
+    let mail = find_mail(&context.store(), &mailbox, &mail)?;
+
+    Ok(Json(mail.attachment_metadata()?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/mailboxes/{mailbox}/mails/{mail}/attachments/{name}",
+    tag = "mails",
+    params(
+        ("mailbox" = String, Path, description = "Id of the mailbox, the address of the recipient"),
+        ("mail" = String, Path, description = "Id of the mail, embedded messages contain a dot"),
+        ("name" = String, Path, description = "File name of the attachment"),
+    ),
+    responses(
+        (status = 200, description = "Content with the content type of the attachment", content_type = "application/octet-stream", body = String),
+        (status = 404, description = "Mail or attachment not found", body = ErrorBody),
+    ),
+)]
+async fn attachment(
+    State(context): State<Context>,
+    ApiPath((mailbox, mail, name)): ApiPath<(String, String, String)>,
+) -> ApiResult<Response> {
+    let mail = find_mail(&context.store(), &mailbox, &mail)?;
+
+    let data = mail
+        .attachments()?
+        .into_iter()
+        .find(|attachment| attachment.id() == name)
+        .ok_or_else(|| ApiError::not_found("attachment not found"))?
+        .data()?;
+
+    let content_type = match mail
+        .attachment_metadata()?
+        .into_iter()
+        .find(|attachment| attachment.name == name)
+    {
+        Some(Attachment {
+            content_type,
+            charset: Some(charset),
+            ..
+        }) if content_type.starts_with("text/") => format!("{}; charset={}", content_type, charset),
+        Some(attachment) => attachment.content_type,
+        None => String::from("application/octet-stream"),
+    };
+
+    Ok(file(&content_type, Some(&name), data))
+}
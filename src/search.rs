@@ -0,0 +1,699 @@
+//! In-memory full-text index over all stored mails.
+//!
+//! The index is built from the store on startup and fed by the mail server
+//! whenever a mail is delivered. Queries consist of whitespace separated
+//! terms, which may be restricted to a field by a prefix (`subject:`,
+//! `from:`, `to:`, `body:` or `mailbox:`). All terms must match, terms
+//! without a field match any field.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use mail_parser::Message;
+
+use crate::mail::{normalize_subject, text_list, MailError, MailItem, Mailboxes};
+
+pub type SharedIndex = Arc<RwLock<Index>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Subject,
+    From,
+    To,
+    Body,
+}
+
+impl Field {
+    const ALL: [Field; 4] = [Field::Subject, Field::From, Field::To, Field::Body];
+
+    fn weight(self) -> f32 {
+        match self {
+            Field::Subject => 3.0,
+            Field::From | Field::To => 2.0,
+            Field::Body => 1.0,
+        }
+    }
+}
+
+/// Number of characters of the body kept for snippets.
+const SNIPPET_SOURCE: usize = 4096;
+const SNIPPET_CONTEXT: usize = 80;
+
+/// Header values later mails of a mailbox are threaded by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ThreadKey {
+    MessageId(String),
+    /// Message-ID referenced by `References` or `In-Reply-To`.
+    Reference(String),
+    /// Normalized subject.
+    Subject(String),
+}
+
+struct Document {
+    mailbox: String,
+    mail: String,
+    subject: String,
+    from: String,
+    date: Option<String>,
+    thread: String,
+    body: String,
+    terms: Vec<String>,
+    thread_keys: Vec<ThreadKey>,
+}
+
+/// Mail matching a query.
+pub struct Hit {
+    pub mailbox: String,
+    pub mail: String,
+    pub thread: String,
+    pub subject: String,
+    pub from: String,
+    pub date: Option<String>,
+    pub snippet: String,
+    pub score: f32,
+}
+
+#[derive(Default)]
+pub struct Index {
+    /// Indexed documents by their number, removed mails leave no gaps
+    /// behind.
+    documents: HashMap<usize, Document>,
+    /// Number of the next inserted document.
+    next: usize,
+    lookup: HashMap<(String, String), usize>,
+    /// Occurrences of a term per document and field.
+    postings: HashMap<String, HashMap<usize, [u32; 4]>>,
+    /// Mails per mailbox and thread key, so delivering a mail does not read
+    /// the whole mailbox.
+    threads: HashMap<(String, ThreadKey), HashSet<usize>>,
+}
+
+impl Index {
+    /// Indexes all mails of the store.
+    pub fn build(mailboxes: &Mailboxes) -> Result<Self, MailError> {
+        let mut index = Self::default();
+
+        for mailbox in mailboxes.mailboxes()? {
+            let id = mailbox.id();
+
+            for mail in mailbox.mails()? {
+                index.insert(&id, &mail)?;
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Adds a mail and all of its embedded messages to the index.
+    pub fn insert(&mut self, mailbox: &str, mail: &MailItem) -> Result<(), MailError> {
+        let metadata = mail.metadata()?;
+        let text = mail.text()?.map(|v| v.content);
+        let html = mail.html()?.map(|v| strip_html(&v.content));
+
+        let to = metadata
+            .to
+            .iter()
+            .chain(metadata.cc.iter())
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let body = text
+            .as_deref()
+            .or(html.as_deref())
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut counts: HashMap<String, [u32; 4]> = HashMap::new();
+        let mut count = |field: Field, value: &str| {
+            for token in tokenize(value) {
+                counts.entry(token).or_default()[field as usize] += 1;
+            }
+        };
+
+        count(Field::Subject, &metadata.subject);
+        count(Field::From, &metadata.from);
+        count(Field::To, &to);
+        count(Field::To, mailbox);
+        for body in [&text, &html].into_iter().flatten() {
+            count(Field::Body, body);
+        }
+
+        // embedded messages are not part of the threads of a mailbox
+        let thread_keys = if metadata.id.contains('.') {
+            Vec::new()
+        } else {
+            let subject = normalize_subject(&metadata.subject);
+
+            metadata
+                .message_id
+                .iter()
+                .cloned()
+                .map(ThreadKey::MessageId)
+                .chain(
+                    metadata
+                        .references
+                        .iter()
+                        .chain(metadata.in_reply_to.iter())
+                        .cloned()
+                        .map(ThreadKey::Reference),
+                )
+                .chain((!subject.is_empty()).then_some(ThreadKey::Subject(subject)))
+                .collect::<Vec<_>>()
+        };
+
+        let key = (mailbox.to_string(), metadata.id.clone());
+        self.remove_document(&key);
+
+        let doc = self.next;
+        self.next += 1;
+        for (term, fields) in &counts {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(doc, *fields);
+        }
+        for thread_key in &thread_keys {
+            self.threads
+                .entry((mailbox.to_string(), thread_key.clone()))
+                .or_default()
+                .insert(doc);
+        }
+
+        self.documents.insert(
+            doc,
+            Document {
+                mailbox: mailbox.to_string(),
+                thread: metadata.thread().to_string(),
+                mail: metadata.id,
+                subject: metadata.subject,
+                from: metadata.from,
+                date: metadata.date,
+                body: body.chars().take(SNIPPET_SOURCE).collect(),
+                terms: counts.into_keys().collect(),
+                thread_keys,
+            },
+        );
+        self.lookup.insert(key, doc);
+
+        for message in mail.messages()? {
+            self.insert(mailbox, &message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes a mail and all of its embedded messages from the index.
+    pub fn remove(&mut self, mailbox: &str, mail: &str) {
+        let nested = format!("{}.", mail);
+
+        self.remove_where(|(other, id)| {
+            other == mailbox && (id == mail || id.starts_with(&nested))
+        });
+    }
+
+    /// Removes all mails of a mailbox from the index.
+    pub fn remove_mailbox(&mut self, mailbox: &str) {
+        self.remove_where(|(other, _)| other == mailbox);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn remove_where(&mut self, f: impl Fn(&(String, String)) -> bool) {
+        let keys = self
+            .lookup
+            .keys()
+            .filter(|key| f(key))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            self.remove_document(&key);
+        }
+    }
+
+    fn remove_document(&mut self, key: &(String, String)) {
+        let doc = match self.lookup.remove(key) {
+            Some(doc) => doc,
+            None => return,
+        };
+
+        if let Some(document) = self.documents.remove(&doc) {
+            for term in document.terms {
+                if let Some(postings) = self.postings.get_mut(&term) {
+                    postings.remove(&doc);
+
+                    if postings.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+            }
+
+            for thread_key in document.thread_keys {
+                let key = (document.mailbox.clone(), thread_key);
+
+                if let Some(docs) = self.threads.get_mut(&key) {
+                    docs.remove(&doc);
+
+                    if docs.is_empty() {
+                        self.threads.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finds the thread a new message delivered to the mailbox belongs to.
+    /// Mails referenced by the message (or referencing it) take precedence,
+    /// otherwise the newest mail with the same normalized subject is used.
+    pub fn thread_of(&self, mailbox: &str, message: &Message) -> Option<String> {
+        let linked = text_list(message.references())
+            .into_iter()
+            .chain(text_list(message.in_reply_to()))
+            .map(ThreadKey::MessageId)
+            .chain(
+                message
+                    .message_id()
+                    .map(|id| ThreadKey::Reference(id.to_string())),
+            );
+        let subject = normalize_subject(message.subject().unwrap_or_default());
+        let subject = (!subject.is_empty()).then_some(ThreadKey::Subject(subject));
+
+        self.newest(mailbox, linked)
+            .or_else(|| self.newest(mailbox, subject))
+            .map(|document| document.thread.clone())
+    }
+
+    /// Newest mail of the mailbox having any of the thread keys.
+    fn newest(
+        &self,
+        mailbox: &str,
+        thread_keys: impl IntoIterator<Item = ThreadKey>,
+    ) -> Option<&Document> {
+        thread_keys
+            .into_iter()
+            .filter_map(|thread_key| self.threads.get(&(mailbox.to_string(), thread_key)))
+            .flatten()
+            .filter_map(|doc| self.documents.get(doc))
+            .max_by(|a, b| a.mail.cmp(&b.mail))
+    }
+
+    /// Returns the best matching mails, ordered by descending score.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<Hit> {
+        let query = Query::parse(query);
+
+        if query.terms.is_empty() && query.mailbox.is_none() {
+            return Vec::new();
+        }
+
+        let total = self.lookup.len() as f32;
+        let mut scores: Option<HashMap<usize, f32>> = None;
+
+        for (field, token) in &query.terms {
+            let mut matches: HashMap<usize, f32> = HashMap::new();
+
+            // tokens also match as prefix, but weaker than exact matches
+            for (term, postings) in self
+                .postings
+                .iter()
+                .filter(|(term, _)| term.starts_with(token.as_str()))
+            {
+                let exact = if term == token { 1.0 } else { 0.5 };
+                let idf = (1.0 + total / postings.len() as f32).ln();
+
+                for (doc, counts) in postings {
+                    let score = Field::ALL
+                        .into_iter()
+                        .filter(|f| field.map(|field| field == *f).unwrap_or(true))
+                        .filter(|f| counts[*f as usize] > 0)
+                        .map(|f| f.weight() * (1.0 + (counts[f as usize] as f32).ln()))
+                        .sum::<f32>()
+                        * idf
+                        * exact;
+
+                    if score > 0.0 {
+                        let entry = matches.entry(*doc).or_default();
+                        *entry = entry.max(score);
+                    }
+                }
+            }
+
+            scores = Some(match scores {
+                None => matches,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(doc, score)| matches.get(&doc).map(|other| (doc, score + other)))
+                    .collect(),
+            });
+        }
+
+        let scores = scores.unwrap_or_else(|| {
+            self.lookup
+                .values()
+                .map(|doc| (*doc, 0.0))
+                .collect::<HashMap<_, _>>()
+        });
+
+        let mut hits = scores
+            .into_iter()
+            .filter_map(|(doc, score)| self.documents.get(&doc).map(|document| (document, score)))
+            .filter(|(document, _)| {
+                query
+                    .mailbox
+                    .as_ref()
+                    .map(|mailbox| document.mailbox.eq_ignore_ascii_case(mailbox))
+                    .unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+
+        hits.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .total_cmp(a_score)
+                .then_with(|| b.mail.cmp(&a.mail))
+                .then_with(|| a.mailbox.cmp(&b.mailbox))
+        });
+
+        let tokens = query
+            .terms
+            .iter()
+            .map(|(_, token)| token.as_str())
+            .collect::<Vec<_>>();
+
+        hits.into_iter()
+            .take(limit)
+            .map(|(document, score)| Hit {
+                mailbox: document.mailbox.clone(),
+                mail: document.mail.clone(),
+                thread: document.thread.clone(),
+                subject: document.subject.clone(),
+                from: document.from.clone(),
+                date: document.date.clone(),
+                snippet: snippet(&document.body, &tokens),
+                score,
+            })
+            .collect()
+    }
+}
+
+struct Query {
+    terms: Vec<(Option<Field>, String)>,
+    mailbox: Option<String>,
+}
+
+impl Query {
+    fn parse(query: &str) -> Self {
+        let mut terms = Vec::new();
+        let mut mailbox = None;
+
+        for word in query.split_whitespace() {
+            let (field, value) = match word.split_once(':') {
+                Some(("subject", value)) => (Some(Field::Subject), value),
+                Some(("from", value)) => (Some(Field::From), value),
+                Some(("to", value)) => (Some(Field::To), value),
+                Some(("body", value)) => (Some(Field::Body), value),
+                Some(("mailbox", value)) => {
+                    mailbox = Some(value.to_string());
+                    continue;
+                }
+                _ => (None, word),
+            };
+
+            let mut seen = HashSet::new();
+            for token in tokenize(value) {
+                if seen.insert(token.clone()) {
+                    terms.push((field, token));
+                }
+            }
+        }
+
+        Self { terms, mailbox }
+    }
+}
+
+fn tokenize(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+}
+
+/// Cuts the part of the body around the first occurrence of a token.
+fn snippet(body: &str, tokens: &[&str]) -> String {
+    let lower = body.to_lowercase();
+    let position = tokens
+        .iter()
+        .filter_map(|token| lower.find(token))
+        .min()
+        .map(|pos| lower[..pos].chars().count())
+        .unwrap_or(0);
+
+    let start = position.saturating_sub(SNIPPET_CONTEXT);
+    let mut snippet = body
+        .chars()
+        .skip(start)
+        .take(SNIPPET_CONTEXT * 2)
+        .collect::<String>();
+
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if body.chars().count() > start + SNIPPET_CONTEXT * 2 {
+        snippet.push('…');
+    }
+
+    snippet
+}
+
+/// Extracts the text of a HTML document, dropping tags, scripts, styles
+/// and comments.
+fn strip_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        out.push_str(&decode_entities(&rest[..start]));
+        rest = &rest[start..];
+
+        let lower = rest
+            .get(..8)
+            .map(|v| v.to_ascii_lowercase())
+            .unwrap_or_default();
+        let end = if rest.starts_with("<!--") {
+            rest.find("-->").map(|pos| pos + 3)
+        } else if lower.starts_with("<script") || lower.starts_with("<style") {
+            let close = if lower.starts_with("<script") {
+                "</script"
+            } else {
+                "</style"
+            };
+            rest.to_ascii_lowercase()
+                .find(close)
+                .and_then(|pos| rest[pos..].find('>').map(|end| pos + end + 1))
+        } else {
+            rest.find('>').map(|pos| pos + 1)
+        };
+
+        match end {
+            Some(end) => {
+                rest = &rest[end..];
+                out.push(' ');
+            }
+            None => {
+                rest = "";
+            }
+        }
+    }
+
+    out.push_str(&decode_entities(rest));
+    out
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|v| v.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+
+            c.map(|c| (c, end + 1))
+        });
+
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes_words() {
+        let tokens = tokenize("Re: Über-Release 2.0, (draft)!").collect::<Vec<_>>();
+
+        assert_eq!(tokens, vec!["re", "über", "release", "2", "0", "draft"]);
+        assert_eq!(tokenize(" -- ").count(), 0);
+    }
+
+    #[test]
+    fn parses_queries() {
+        let query = Query::parse("subject:Invoice invoice mailbox:john@example.com body:due-date");
+
+        assert_eq!(
+            query.terms,
+            vec![
+                (Some(Field::Subject), String::from("invoice")),
+                (None, String::from("invoice")),
+                (Some(Field::Body), String::from("due")),
+                (Some(Field::Body), String::from("date")),
+            ]
+        );
+        assert_eq!(query.mailbox.as_deref(), Some("john@example.com"));
+    }
+
+    #[test]
+    fn strips_html() {
+        let html = "<html><head><style>p { color: red }</style>\
+            <SCRIPT type=\"text/javascript\">alert('<b>')</SCRIPT></head>\
+            <body><!-- hidden <p> --><p>Fish &amp; chips</p>&lt;3 &#x263A;&#33; &bogus; &</body></html>";
+
+        let text = strip_html(html);
+
+        assert_eq!(
+            text.split_whitespace().collect::<Vec<_>>(),
+            vec!["Fish", "&", "chips", "<3", "☺!", "&bogus;", "&"]
+        );
+        assert_eq!(strip_html("unclosed <b"), "unclosed ");
+    }
+
+    #[test]
+    fn cuts_snippets() {
+        let body = format!("{}Needle ünd more{}", "ä".repeat(100), "ö".repeat(100));
+
+        let cut = snippet(&body, &["needle"]);
+
+        assert!(cut.starts_with('…'));
+        assert!(cut.ends_with('…'));
+        assert_eq!(cut.chars().count(), SNIPPET_CONTEXT * 2 + 2);
+        assert!(cut.contains("Needle ünd more"));
+
+        assert_eq!(snippet("short body", &["missing"]), "short body");
+    }
+
+    #[test]
+    fn finds_threads() {
+        let path = std::env::temp_dir().join(format!(
+            "mail-blackhole-search-threads-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+
+        let deliver = |index: &mut Index, id: &str, headers: &str| {
+            let raw = format!("{}\r\n\r\nbody\r\n", headers);
+            let message = Message::parse(raw.as_bytes()).unwrap();
+            let thread = index.thread_of("test", &message);
+            let mail = path.join("test").join(id);
+
+            std::fs::create_dir_all(&mail).unwrap();
+            let mail = MailItem::create(
+                mail,
+                &message,
+                message.subject().unwrap_or_default().to_string(),
+                Some(thread.clone().unwrap_or_else(|| id.to_string())),
+            )
+            .unwrap();
+            index.insert("test", &mail).unwrap();
+
+            thread
+        };
+
+        let mut index = Index::default();
+
+        assert_eq!(
+            deliver(&mut index, "1", "Message-ID: <a@x>\r\nSubject: Plans"),
+            None
+        );
+        assert_eq!(
+            deliver(&mut index, "2", "Message-ID: <b@x>\r\nSubject: Other"),
+            None
+        );
+        // the reference wins over the subject
+        assert_eq!(
+            deliver(
+                &mut index,
+                "3",
+                "Message-ID: <c@x>\r\nIn-Reply-To: <b@x>\r\nSubject: Re: Plans"
+            ),
+            Some(String::from("2"))
+        );
+        assert_eq!(
+            deliver(&mut index, "4", "Subject: AW: [list] plans"),
+            Some(String::from("2"))
+        );
+        // mails delivered before the mail they reply to
+        assert_eq!(
+            deliver(
+                &mut index,
+                "5",
+                "Message-ID: <e@x>\r\nReferences: <d@x>\r\nSubject: Unrelated"
+            ),
+            None
+        );
+        assert_eq!(
+            deliver(&mut index, "6", "Message-ID: <d@x>\r\nSubject: Start"),
+            Some(String::from("5"))
+        );
+        assert_eq!(
+            index.thread_of("other", &Message::parse(b"Subject: Plans\r\n\r\n").unwrap()),
+            None
+        );
+
+        index.remove("test", "4");
+        index.remove("test", "3");
+        assert_eq!(
+            deliver(&mut index, "7", "Subject: Re: Plans"),
+            Some(String::from("1"))
+        );
+
+        // removed mails leave nothing behind
+        index.remove_mailbox("test");
+        assert!(index.documents.is_empty());
+        assert!(index.lookup.is_empty());
+        assert!(index.postings.is_empty());
+        assert!(index.threads.is_empty());
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
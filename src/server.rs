@@ -0,0 +1,460 @@
+//! Blackhole running within another program, e.g. inside of tests.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use mail_blackhole::server::Server;
+//!
+//! let server = Server::builder()
+//!     .on_event(|event| println!("{:?}", event))
+//!     .start()
+//!     .await?;
+//!
+//! // deliver mails to `server.smtp_addr()`, browse `server.url()`
+//!
+//! server.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::{broadcast::error::RecvError, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::events::{Events, Replay};
+use crate::mail::{MailError, Mailboxes};
+use crate::search::Index;
+use crate::store::Store;
+use crate::{Args, QueueItem};
+
+/// Time open connections, e.g. event streams, get to finish on shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Hook = Box<dyn Fn(&QueueItem) + Send + Sync>;
+
+/// Where the server stores the mailboxes.
+#[derive(Debug, Clone)]
+pub enum Storage {
+    /// Fresh directory which is removed on shutdown.
+    Temporary,
+    /// Existing mails within the directory are kept and served.
+    Directory(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum ServerError {
+    Io(std::io::Error),
+    Mail(MailError),
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServerError::Io(err) => Some(err),
+            ServerError::Mail(err) => Some(err),
+        }
+    }
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ServerError::Io(err) => write!(f, "{}", err),
+            ServerError::Mail(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for ServerError {
+    fn from(err: std::io::Error) -> Self {
+        ServerError::Io(err)
+    }
+}
+
+impl From<MailError> for ServerError {
+    fn from(err: MailError) -> Self {
+        ServerError::Mail(err)
+    }
+}
+
+pub struct ServerBuilder {
+    storage: Storage,
+    smtp_addr: String,
+    http_addr: String,
+    files: Option<PathBuf>,
+    hooks: Vec<Hook>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            storage: Storage::Temporary,
+            smtp_addr: String::from("127.0.0.1:0"),
+            http_addr: String::from("127.0.0.1:0"),
+            files: None,
+            hooks: Vec::new(),
+        }
+    }
+}
+
+/// Configuration of the standalone server.
+impl From<&Args> for ServerBuilder {
+    fn from(args: &Args) -> Self {
+        let builder = Self::default()
+            .storage(Storage::Directory(args.mailboxes.clone()))
+            .smtp_addr(&args.listen_mail)
+            .http_addr(&args.listen_http);
+
+        #[cfg(not(feature = "bundle"))]
+        let builder = builder.files(&args.files);
+
+        builder
+    }
+}
+
+impl ServerBuilder {
+    /// Storage of the mailboxes (default: temporary directory).
+    pub fn storage(mut self, storage: Storage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Listener address of the SMTP server (default: 127.0.0.1:0, any free
+    /// port).
+    pub fn smtp_addr(mut self, addr: impl Into<String>) -> Self {
+        self.smtp_addr = addr.into();
+        self
+    }
+
+    /// Listener address of the HTTP server (default: 127.0.0.1:0, any free
+    /// port).
+    pub fn http_addr(mut self, addr: impl Into<String>) -> Self {
+        self.http_addr = addr.into();
+        self
+    }
+
+    /// Directory containing the web files. Without it, only the APIs and the
+    /// server rendered pages are usable. Bundled builds ignore it.
+    pub fn files(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files = Some(path.into());
+        self
+    }
+
+    /// Calls the hook for every change of the store, in order. Hooks run on
+    /// a task of their own. Events the hooks fell behind on are replayed from
+    /// the journal, only hooks lagging by more than
+    /// [`JOURNAL_SIZE`](crate::events::JOURNAL_SIZE) events miss some, which
+    /// is logged.
+    pub fn on_event(mut self, hook: impl Fn(&QueueItem) + Send + Sync + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Binds both listeners and starts serving in the background.
+    pub async fn start(self) -> Result<Server, ServerError> {
+        let (mailboxes, temporary) = match self.storage {
+            Storage::Temporary => {
+                let path = temporary_dir();
+                (path.clone(), Some(path))
+            }
+            Storage::Directory(path) => (path, None),
+        };
+
+        match Self::spawn(
+            mailboxes,
+            self.smtp_addr,
+            self.http_addr,
+            self.files,
+            self.hooks,
+        )
+        .await
+        {
+            Ok(mut server) => {
+                server.temporary = temporary;
+                Ok(server)
+            }
+            Err(err) => {
+                if let Some(path) = temporary {
+                    let _ = std::fs::remove_dir_all(path);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn spawn(
+        mailboxes: PathBuf,
+        smtp_addr: String,
+        http_addr: String,
+        files: Option<PathBuf>,
+        hooks: Vec<Hook>,
+    ) -> Result<Server, ServerError> {
+        std::fs::create_dir_all(&mailboxes)?;
+
+        let events = Events::default();
+        let index = Index::build(&Mailboxes {
+            path: mailboxes.clone(),
+        })?;
+        let index = Arc::new(std::sync::RwLock::new(index));
+
+        let smtp = tokio::net::TcpListener::bind(&smtp_addr).await?;
+        let http = std::net::TcpListener::bind(&http_addr)?;
+        let smtp_addr = smtp.local_addr()?;
+        let http_addr = http.local_addr()?;
+
+        // subscribe before serving, so the hooks see every event
+        let (stop_hooks, hooks_stopped) = oneshot::channel::<()>();
+        let hooks = spawn_hooks(&events, hooks, hooks_stopped);
+        let (shutdown, signal) = oneshot::channel();
+
+        let task = {
+            let mailboxes = mailboxes.clone();
+            let events = events.clone();
+            let index = index.clone();
+
+            tokio::spawn(async move {
+                let (stop_http, http_stopped) = oneshot::channel::<()>();
+                // the errors are not `Send`, they must not live across the awaits below
+                let http = async {
+                    crate::http::serve(
+                        http,
+                        &mailboxes,
+                        files.as_deref(),
+                        events.clone(),
+                        index.clone(),
+                        async {
+                            let _ = http_stopped.await;
+                        },
+                    )
+                    .await
+                    .map_err(|err| err.to_string())
+                };
+                tokio::pin!(http);
+
+                let mail = async {
+                    crate::mail::serve(smtp, mailboxes.clone(), events.clone(), index.clone())
+                        .await
+                        .map_err(|err| err.to_string())
+                };
+
+                tokio::select! {
+                    val = &mut http => {
+                        if let Err(err) = val {
+                            println!("http server failed: {}", err);
+                        }
+                    }
+                    val = mail => {
+                        if let Err(err) = val {
+                            println!("mail server failed: {}", err);
+                        }
+                    }
+                    _ = signal => {
+                        let _ = stop_http.send(());
+                        let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, http).await;
+                    }
+                }
+            })
+        };
+
+        Ok(Server {
+            smtp_addr,
+            http_addr,
+            store: Store {
+                mailboxes: Mailboxes { path: mailboxes },
+                index,
+                events,
+            },
+            shutdown: Some(shutdown),
+            task: Some(task),
+            stop_hooks: Some(stop_hooks),
+            hooks: Some(hooks),
+            temporary: None,
+        })
+    }
+}
+
+/// Passes all events published from now on to the hooks, until stopped.
+fn spawn_hooks(
+    events: &Events,
+    hooks: Vec<Hook>,
+    stopped: oneshot::Receiver<()>,
+) -> JoinHandle<()> {
+    let events = events.clone();
+    let (_, mut receiver) = events.subscribe(None);
+    let mut last = events.latest();
+
+    tokio::spawn(async move {
+        tokio::pin!(stopped);
+
+        loop {
+            // pending events are passed to the hooks before stopping
+            tokio::select! {
+                biased;
+                val = receiver.recv() => match val {
+                    Ok(envelope) => {
+                        last = envelope.id;
+                        hooks.iter().for_each(|hook| hook(&envelope.item));
+                    }
+                    // the channel dropped events, the journal keeps more of them
+                    Err(RecvError::Lagged(_)) => {
+                        let (replay, next) = events.subscribe(Some(last));
+                        receiver = next;
+
+                        match replay {
+                            Replay::Events(missed) => {
+                                for envelope in missed {
+                                    last = envelope.id;
+                                    hooks.iter().for_each(|hook| hook(&envelope.item));
+                                }
+                            }
+                            Replay::Resync(latest) => {
+                                println!("event hooks missed {} events", latest - last);
+                                last = latest;
+                            }
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut stopped => break,
+            }
+        }
+    })
+}
+
+/// Handle of a running server. Dropping it stops the server without waiting
+/// for open connections.
+pub struct Server {
+    smtp_addr: SocketAddr,
+    http_addr: SocketAddr,
+    store: Store,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+    stop_hooks: Option<oneshot::Sender<()>>,
+    hooks: Option<JoinHandle<()>>,
+    temporary: Option<PathBuf>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Bound address of the SMTP server.
+    pub fn smtp_addr(&self) -> SocketAddr {
+        self.smtp_addr
+    }
+
+    /// Bound address of the HTTP server.
+    pub fn http_addr(&self) -> SocketAddr {
+        self.http_addr
+    }
+
+    /// Base URL of the HTTP server, e.g. for `client::Client`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.http_addr)
+    }
+
+    /// Directory containing the mailboxes.
+    pub fn mailboxes_path(&self) -> &Path {
+        &self.store.mailboxes.path
+    }
+
+    /// Direct access to the stored mails. Changes are published to all
+    /// connected clients.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Waits until the server stops on its own, i.e. one of its listeners
+    /// failed.
+    pub async fn wait(mut self) {
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+
+    /// Stops accepting mails, waits for open HTTP connections and the hooks
+    /// and removes a temporary storage.
+    pub async fn shutdown(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+        if let Some(stop_hooks) = self.stop_hooks.take() {
+            let _ = stop_hooks.send(());
+        }
+        if let Some(hooks) = self.hooks.take() {
+            let _ = hooks.await;
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if let Some(hooks) = self.hooks.take() {
+            hooks.abort();
+        }
+        if let Some(path) = self.temporary.take() {
+            let _ = std::fs::remove_dir_all(path);
+        }
+    }
+}
+
+fn temporary_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+    std::env::temp_dir().join(format!(
+        "mail-blackhole-{}-{}-{}",
+        std::process::id(),
+        time.as_nanos(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replays_events_the_hooks_fell_behind_on() {
+        let events = Events::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook: Hook = {
+            let seen = seen.clone();
+            Box::new(move |event| {
+                if let QueueItem::MailboxCreated { mailbox } = event {
+                    seen.lock().unwrap().push(mailbox.clone());
+                }
+            })
+        };
+
+        let (stop, stopped) = oneshot::channel();
+        let hooks = spawn_hooks(&events, vec![hook], stopped);
+
+        // more events than the channel holds, published before the hooks run
+        let published = (0..1000).map(|i| i.to_string()).collect::<Vec<_>>();
+        for mailbox in &published {
+            events.publish(QueueItem::MailboxCreated {
+                mailbox: mailbox.clone(),
+            });
+        }
+
+        stop.send(()).unwrap();
+        hooks.await.unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), published);
+    }
+}
@@ -0,0 +1,194 @@
+//! Changes of the stored mails shared by all interfaces.
+//!
+//! Every change keeps the search index up to date and is published as event,
+//! so all connected clients stay in sync.
+
+use crate::events::Events;
+use crate::mail::{MailError, Mailboxes};
+use crate::search::SharedIndex;
+use crate::QueueItem;
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// The request is invalid, e.g. a change which is not allowed.
+    Invalid(String),
+    Mail(MailError),
+    IndexPoisoned,
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Mail(err) => Some(err),
+            StoreError::Invalid(_) | StoreError::IndexPoisoned => None,
+        }
+    }
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            StoreError::Invalid(reason) => write!(f, "{}", reason),
+            StoreError::Mail(err) => write!(f, "{}", err),
+            StoreError::IndexPoisoned => write!(f, "search index is poisoned"),
+        }
+    }
+}
+
+impl From<MailError> for StoreError {
+    fn from(err: MailError) -> Self {
+        StoreError::Mail(err)
+    }
+}
+
+pub struct Store {
+    pub mailboxes: Mailboxes,
+    pub index: SharedIndex,
+    pub events: Events,
+}
+
+impl Store {
+    /// Marks a mail as read or unread. Returns false if the mail does not
+    /// exist.
+    pub fn set_read(&self, mailbox: &str, mail: &str, read: bool) -> Result<bool, StoreError> {
+        let item = match self.mailboxes.mailbox(mailbox)? {
+            Some(item) => item.mail(mail)?,
+            None => None,
+        };
+        let item = match item {
+            Some(item) => item,
+            None => return Ok(false),
+        };
+
+        if item.read()? != read {
+            if read {
+                item.set_read()?;
+            } else {
+                item.set_unread()?;
+            }
+
+            let mailbox = mailbox.to_string();
+            let mails = vec![mail.to_string()];
+
+            self.events.publish(if read {
+                QueueItem::MailRead { mailbox, mails }
+            } else {
+                QueueItem::MailUnread { mailbox, mails }
+            });
+        }
+
+        Ok(true)
+    }
+
+    /// Marks all mails of a mailbox as read. Returns false if the mailbox
+    /// does not exist.
+    pub fn mark_mailbox_read(&self, mailbox: &str) -> Result<bool, StoreError> {
+        let mails = match self.mailboxes.mailbox(mailbox)? {
+            Some(item) => item.mark_read()?,
+            None => return Ok(false),
+        };
+
+        if !mails.is_empty() {
+            self.events.publish(QueueItem::MailRead {
+                mailbox: mailbox.to_string(),
+                mails,
+            });
+        }
+
+        Ok(true)
+    }
+
+    /// Deletes a mail. Embedded messages are part of their mail and can not
+    /// be deleted on their own. Returns false if the mail does not exist.
+    pub fn delete_mail(&self, mailbox: &str, mail: &str) -> Result<bool, StoreError> {
+        if mail.contains('.') {
+            return Err(StoreError::Invalid(String::from(
+                "embedded messages can not be deleted",
+            )));
+        }
+
+        let item = match self.mailboxes.mailbox(mailbox)? {
+            Some(item) => item.mail(mail)?,
+            None => None,
+        };
+        let item = match item {
+            Some(item) => item,
+            None => return Ok(false),
+        };
+
+        let unread = if item.read()? { 0 } else { 1 };
+
+        item.delete()?;
+        self.index
+            .write()
+            .map_err(|_| StoreError::IndexPoisoned)?
+            .remove(mailbox, mail);
+
+        self.events.publish(QueueItem::MailDeleted {
+            mailbox: mailbox.to_string(),
+            mails: vec![mail.to_string()],
+            unread,
+        });
+
+        Ok(true)
+    }
+
+    /// Deletes all mails of a mailbox but keeps the mailbox. Returns false
+    /// if the mailbox does not exist.
+    pub fn empty_mailbox(&self, mailbox: &str) -> Result<bool, StoreError> {
+        let (mails, unread) = match self.mailboxes.mailbox(mailbox)? {
+            Some(item) => {
+                let unread = item.unread()?;
+                (item.empty()?, unread)
+            }
+            None => return Ok(false),
+        };
+
+        self.index
+            .write()
+            .map_err(|_| StoreError::IndexPoisoned)?
+            .remove_mailbox(mailbox);
+
+        self.events.publish(QueueItem::MailDeleted {
+            mailbox: mailbox.to_string(),
+            mails,
+            unread,
+        });
+
+        Ok(true)
+    }
+
+    /// Deletes a mailbox with all of its mails. Returns false if the
+    /// mailbox does not exist.
+    pub fn delete_mailbox(&self, mailbox: &str) -> Result<bool, StoreError> {
+        match self.mailboxes.mailbox(mailbox)? {
+            Some(item) => item.delete()?,
+            None => return Ok(false),
+        }
+
+        self.index
+            .write()
+            .map_err(|_| StoreError::IndexPoisoned)?
+            .remove_mailbox(mailbox);
+
+        self.events.publish(QueueItem::MailboxRemoved {
+            mailbox: mailbox.to_string(),
+        });
+
+        Ok(true)
+    }
+
+    /// Deletes all mailboxes.
+    pub fn purge(&self) -> Result<(), StoreError> {
+        self.mailboxes.purge()?;
+
+        self.index
+            .write()
+            .map_err(|_| StoreError::IndexPoisoned)?
+            .clear();
+
+        self.events.publish(QueueItem::StorePurged);
+
+        Ok(())
+    }
+}
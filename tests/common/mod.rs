@@ -0,0 +1,51 @@
+//! Helpers shared by the integration tests.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+
+/// Sends the mail over SMTP, panics if the server does not accept it.
+pub fn send_mail(addr: SocketAddr, mailbox: &str, data: &[u8]) {
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+
+    let mut expect = |code: &str| loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+
+        assert!(line.starts_with(code), "expected {}, got `{}`", code, line);
+
+        // the last line of a reply has a space after the code
+        if line.as_bytes().get(3) != Some(&b'-') {
+            break;
+        }
+    };
+
+    expect("220");
+    for (command, code) in [
+        (String::from("EHLO localhost"), "250"),
+        (String::from("MAIL FROM:<sender@example.com>"), "250"),
+        (format!("RCPT TO:<{}>", mailbox), "250"),
+        (String::from("DATA"), "354"),
+    ] {
+        write!(writer, "{}\r\n", command).unwrap();
+        expect(code);
+    }
+
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    for line in data.split(|b| *b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        // lines starting with a dot are escaped by another dot
+        if line.starts_with(b".") {
+            writer.write_all(b".").unwrap();
+        }
+        writer.write_all(line).unwrap();
+        writer.write_all(b"\r\n").unwrap();
+    }
+    writer.write_all(b".\r\n").unwrap();
+    expect("250");
+
+    writer.write_all(b"QUIT\r\n").unwrap();
+    expect("221");
+}
@@ -0,0 +1,174 @@
+//! Requests against the HTTP endpoints of an embedded server.
+
+use mail_blackhole::api::{encode_path_segment, ErrorBody, MailboxQuery};
+use mail_blackhole::client::Client;
+use mail_blackhole::server::Server;
+
+mod common;
+
+const MAILBOX: &str = "receiver@example.com";
+
+/// Starts a server and delivers the mail to [`MAILBOX`].
+async fn server_with_mail(data: &'static [u8]) -> Server {
+    let server = Server::builder().start().await.unwrap();
+    let addr = server.smtp_addr();
+
+    tokio::task::spawn_blocking(move || common::send_mail(addr, MAILBOX, data))
+        .await
+        .unwrap();
+
+    server
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_attachments_in_their_charset() {
+    let server = server_with_mail(
+        b"From: sender@example.com\r\n\
+        To: receiver@example.com\r\n\
+        Subject: Export\r\n\
+        Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+        \r\n\
+        --b\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        see attachment\r\n\
+        --b\r\n\
+        Content-Type: text/csv; charset=iso-8859-1\r\n\
+        Content-Transfer-Encoding: 8bit\r\n\
+        Content-Disposition: attachment; filename=\"export.csv\"\r\n\
+        \r\n\
+        name;city\r\n\
+        M\xfcller;K\xf6ln\r\n\
+        --b--\r\n",
+    )
+    .await;
+    let latin1 = b"name;city\r\nM\xfcller;K\xf6ln";
+
+    let mail = Client::new(server.url())
+        .assert_single_mail(MAILBOX, &MailboxQuery::default())
+        .await;
+    let id = &mail.metadata.id;
+    assert_eq!(mail.attachments[0].size, latin1.len());
+
+    for url in [
+        format!(
+            "{}/api/v1/mailboxes/{}/mails/{}/attachments/export.csv",
+            server.url(),
+            MAILBOX,
+            id
+        ),
+        format!(
+            "{}/data/{}/{}/attachments/export.csv",
+            server.url(),
+            MAILBOX,
+            id
+        ),
+    ] {
+        let response = reqwest::get(&url).await.unwrap();
+
+        assert_eq!(response.status(), 200, "{}", url);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/csv; charset=iso-8859-1",
+            "{}",
+            url
+        );
+        assert_eq!(&response.bytes().await.unwrap()[..], latin1, "{}", url);
+    }
+
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_attachments_with_special_names() {
+    let server = server_with_mail(
+        b"From: sender@example.com\r\n\
+        To: receiver@example.com\r\n\
+        Subject: Invoice\r\n\
+        Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+        \r\n\
+        --b\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        see attachment\r\n\
+        --b\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        Content-Disposition: attachment; filename=\"invoice #3?.txt\"\r\n\
+        \r\n\
+        total: 3\r\n\
+        --b--\r\n",
+    )
+    .await;
+
+    let mail = Client::new(server.url())
+        .assert_single_mail(MAILBOX, &MailboxQuery::default())
+        .await;
+    let name = &mail.attachments[0].name;
+    assert_eq!(name, "invoice #3?.txt");
+
+    let url = format!(
+        "{}/data/{}/{}/attachments/{}",
+        server.url(),
+        encode_path_segment(MAILBOX),
+        mail.metadata.id,
+        encode_path_segment(name)
+    );
+    let response = reqwest::get(&url).await.unwrap();
+
+    assert_eq!(response.status(), 200, "{}", url);
+    assert_eq!(response.text().await.unwrap(), "total: 3");
+
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_invalid_paths_with_json_errors() {
+    let server = Server::builder().start().await.unwrap();
+
+    for path in [
+        "/api/v1/mailboxes/%FF",
+        "/api/v1/mailboxes/%FF/mails/1",
+        "/api/v1/mailboxes/receiver@example.com/mails/1/parts/first",
+    ] {
+        let url = format!("{}{}", server.url(), path);
+        let response = reqwest::get(&url).await.unwrap();
+
+        assert_eq!(response.status(), 400, "{}", path);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/json",
+            "{}",
+            path
+        );
+
+        let body: ErrorBody = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!(body.status, 400, "{}", path);
+        assert!(!body.error.is_empty(), "{}", path);
+    }
+
+    server.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_openapi_document_and_explorer() {
+    let server = Server::builder().start().await.unwrap();
+
+    let response = reqwest::get(format!("{}/api/v1/openapi.json", server.url()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let document: serde_json::Value =
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+    assert!(document["paths"]["/api/v1/mailboxes"].is_object());
+
+    let response = reqwest::get(format!("{}/api/v1/docs", server.url()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+
+    server.shutdown().await;
+}
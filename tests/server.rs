@@ -0,0 +1,69 @@
+//! Delivers a mail to an embedded server and reads it back through the REST
+//! API.
+
+use std::sync::{Arc, Mutex};
+
+use mail_blackhole::api::MailboxQuery;
+use mail_blackhole::client::Client;
+use mail_blackhole::server::Server;
+use mail_blackhole::QueueItem;
+
+mod common;
+
+const MAILBOX: &str = "receiver@example.com";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn delivers_mail_to_embedded_server() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let server = {
+        let events = events.clone();
+
+        Server::builder()
+            .on_event(move |event| events.lock().unwrap().push(event.clone()))
+            .start()
+            .await
+            .unwrap()
+    };
+    let mailboxes = server.mailboxes_path().to_path_buf();
+    assert!(mailboxes.is_dir());
+
+    let addr = server.smtp_addr();
+    let data = std::fs::read("tests/with-attachment.mail").unwrap();
+    tokio::task::spawn_blocking(move || common::send_mail(addr, MAILBOX, &data))
+        .await
+        .unwrap();
+
+    let client = Client::new(server.url());
+    let mail = client
+        .assert_single_mail(MAILBOX, &MailboxQuery::default())
+        .await;
+
+    assert_eq!(mail.metadata.subject, "Test Subject");
+    assert_eq!(mail.metadata.from, "tender@domain.com");
+    assert!(mail.body_contains("cool black square"));
+    assert_eq!(mail.attachments.len(), 1);
+    assert_eq!(mail.attachments[0].name, "black.png");
+    assert_eq!(mail.attachments[0].content_type, "image/png");
+
+    let png = client
+        .attachment(MAILBOX, &mail.metadata.id, "black.png")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(png.starts_with(b"\x89PNG"));
+
+    server.shutdown().await;
+
+    assert!(!mailboxes.exists());
+
+    // the hooks get all events published before the shutdown
+    let events = events.lock().unwrap();
+    assert!(matches!(
+        events.as_slice(),
+        [
+            QueueItem::MailboxCreated { mailbox },
+            QueueItem::MailCreated { mail, .. },
+            ..
+        ] if mailbox == MAILBOX && mail.subject == "Test Subject"
+    ));
+}